@@ -0,0 +1,34 @@
+use std::collections::VecDeque;
+
+use serde_json::{json, Value};
+use chrono::Utc;
+
+static MAX_ENTRIES: usize = 10_000;
+
+/// Timestamped record of everything that happened in a game, kept for
+/// arbiters. The oldest entries are dropped once the log is full.
+pub struct ActivityLog {
+    entries: VecDeque<Value>,
+}
+
+impl ActivityLog {
+    pub fn new() -> Self {
+        ActivityLog { entries: VecDeque::new() }
+    }
+
+    pub fn record(&mut self, event: &str, details: Value) {
+        if self.entries.len() >= MAX_ENTRIES {
+            let _ = self.entries.pop_front();
+        }
+
+        self.entries.push_back(json!({
+            "timestamp": Utc::now().timestamp_millis(),
+            "event": event,
+            "details": details,
+        }));
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!(self.entries)
+    }
+}
@@ -1,135 +1,454 @@
-use chess::{Board, Piece, Color};
-
-use serde_json::json;
-
-static FIVE_MINUTES:i64 = 5 * 60 * 1000;
-
-pub struct ChessGame {
-    pub board: Board,
-    pub white_sp: [i32; 5],
-    pub black_sp: [i32; 5],
-    white_time: i64,
-    black_time: i64,
-    turn: Color,
-    last_move_capture: bool,
-    last_time_sum: i64,
-    last_move: String,
-}
-
-impl ChessGame {
-    pub fn new() -> Self {
-        ChessGame {
-            board: Board::default(),
-            white_sp: [0; 5],
-            black_sp: [0; 5],
-            white_time: FIVE_MINUTES,
-            black_time: FIVE_MINUTES,
-            turn: Color::White,
-            last_move_capture: false,
-            last_time_sum: 0,
-            last_move: String::new(),
-        }
-    }
-
-    pub fn last_move_capture(&mut self, capture: bool) {
-        self.last_move_capture = capture;
-    }
-
-    pub fn flagged(&self) -> bool {
-        self.white_time == 0 || self.black_time == 0
-    }
-
-    pub fn should_update(&mut self) -> bool {
-        let old_time_sum = self.last_time_sum;
-        self.last_time_sum = (self.white_time + 999) / 1_000 
-            + (self.black_time + 999) / 1_000;
-
-        old_time_sum != self.last_time_sum
-    }
-
-    pub fn synchronize_time(&mut self, time_diff: i64) {
-        match self.turn {
-            Color::White => self.white_time -= time_diff,
-            _ => self.black_time -= time_diff,
-        };
-
-        self.white_time = self.white_time.max(0);
-        self.black_time = self.black_time.max(0);
-    }
-
-    pub fn change_turn(&mut self, chess_move: String) {
-        self.turn = match self.turn {
-            Color::White => Color::Black,
-            _ => Color::White,
-        };
-
-        self.last_move = chess_move;
-        let _ = self.should_update();
-    }
-
-    pub fn to_string(&self) -> String {
-        let time_white_seconds = (self.white_time + 999) / 1000;
-        let time_black_seconds = (self.black_time + 999) / 1000;
-
-        json!({
-            "fen": self.board.to_string(),
-            "last_move_capture": self.last_move_capture,
-            "white_sp": self.white_sp,
-            "black_sp": self.black_sp,
-            "white_time": format!("{}:{:02}", time_white_seconds / 60, time_white_seconds % 60),
-            "black_time": format!("{}:{:02}", time_black_seconds / 60, time_black_seconds % 60),
-            "last_move": self.last_move,
-        }).to_string()
-    }
-
-    pub fn add_piece(&mut self, color: &Color, piece: Piece) {
-        let sp_array = match color {
-            Color::Black => &mut self.white_sp,
-            _ => &mut self.black_sp,
-        };
-
-        let i = match piece {
-            Piece::Queen => 0,
-            Piece::Rook => 1,
-            Piece::Bishop => 2,
-            Piece::Knight => 3,
-            Piece::Pawn => 4,
-            _ => return,
-        };
-
-        sp_array[i] += 1;
-    }
-
-    pub fn add_pawn(&mut self, color: &Color) {
-        let sp_array = match color {
-            Color::White => &mut self.white_sp,
-            _ => &mut self.black_sp,
-        };
-
-        sp_array[4] += 1;
-    }
-
-    pub fn decrease_count(&mut self, color: &Color, piece: Piece) -> bool {
-        let sp_array = match color {
-            Color::White => &mut self.white_sp,
-            _ => &mut self.black_sp,
-        };
-
-        let i = match piece {
-            Piece::Queen => 0,
-            Piece::Rook => 1,
-            Piece::Bishop => 2,
-            Piece::Knight => 3,
-            Piece::Pawn => 4,
-            _ => return false,
-        };
-
-        if sp_array[i] <= 0 {
-            return false;
-        }
-
-        sp_array[i] -= 1;
-
-        true
-    }
-}
\ No newline at end of file
+use std::fmt;
+
+use chess::{Board, Piece, Color};
+
+use serde_json::{json, Value};
+
+use crate::game_server::settings::{DecayingIncrement, TimeControl};
+
+/// Moves made with less than this on the clock count as time pressure.
+static TIME_PRESSURE_MS: i64 = 5 * 1000;
+
+/// Everything a client needs to re-animate the last move of a board.
+#[derive(Clone, Debug)]
+pub struct LastMove {
+    pub source: String,
+    pub target: String,
+    pub piece: Piece,
+    pub color: Color,
+    pub capture: Option<Piece>,
+    pub promotion: Option<Piece>,
+    pub drop: bool,
+    /// The move in standard algebraic notation, e.g. `Nxe5+` or `N@e6`.
+    pub san: String,
+}
+
+impl LastMove {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "from": self.source,
+            "to": self.target,
+            "piece": piece_name(self.piece, self.color),
+            "capture": self.capture.is_some(),
+            "captured": self.capture.map(|piece| piece_name(piece, !self.color)),
+            "promotion": self.promotion.map(|piece| piece.to_string(Color::White)),
+            "drop": self.drop,
+            "san": self.san,
+        })
+    }
+}
+
+impl fmt::Display for LastMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.source, self.target)
+    }
+}
+
+pub struct ChessGame {
+    pub board: Board,
+    pub white_sp: [i32; 5],
+    pub black_sp: [i32; 5],
+    white_time: i64,
+    black_time: i64,
+    turn: Color,
+    last_time_sum: i64,
+    last_move: Option<LastMove>,
+    /// Moves made so far, indexed by `Color::to_index`.
+    moves_made: [u32; 2],
+    berserk: [bool; 2],
+    /// Moves made under `TIME_PRESSURE_MS`, indexed by `Color::to_index`.
+    time_pressure: [u32; 2],
+    /// Full-move number, bumped after each black move.
+    move_number: u32,
+    /// Half-moves since the last capture or pawn move, drops of pawns included.
+    halfmove: u32,
+    /// Starting time of both clocks, also the most `add_time` fills up to.
+    base_ms: i64,
+    /// Fischer increment earned by every move, the first one included. Unlike
+    /// bonuses it goes through `add_increment`, so it may build time past
+    /// `base_ms`.
+    increment_ms: i64,
+    /// Every move since `start_fen`, in order.
+    history: Vec<LastMove>,
+    /// Position and move number the history starts from, which only differ
+    /// from the initial position after an arbiter set one up.
+    start_fen: String,
+    start_move_number: u32,
+}
+
+impl ChessGame {
+    pub fn new(time_control: TimeControl) -> Self {
+        ChessGame {
+            board: Board::default(),
+            white_sp: [0; 5],
+            black_sp: [0; 5],
+            white_time: time_control.base_ms,
+            black_time: time_control.base_ms,
+            turn: Color::White,
+            last_time_sum: 0,
+            last_move: None,
+            moves_made: [0; 2],
+            berserk: [false; 2],
+            time_pressure: [0; 2],
+            move_number: 1,
+            halfmove: 0,
+            base_ms: time_control.base_ms,
+            increment_ms: time_control.increment_ms,
+            history: Vec::new(),
+            start_fen: Board::default().to_string(),
+            start_move_number: 1,
+        }
+    }
+
+    pub fn flagged(&self) -> Option<Color> {
+        if self.white_time == 0 {
+            Some(Color::White)
+        } else if self.black_time == 0 {
+            Some(Color::Black)
+        } else {
+            None
+        }
+    }
+
+    pub fn should_update(&mut self) -> bool {
+        let old_time_sum = self.last_time_sum;
+        self.last_time_sum = (self.white_time + 999) / 1_000 
+            + (self.black_time + 999) / 1_000;
+
+        old_time_sum != self.last_time_sum
+    }
+
+    /// Runs the clock of the side to move, unless it is still within its
+    /// first `free_moves` moves.
+    pub fn synchronize_time(&mut self, time_diff: i64, free_moves: u32) {
+        if self.moves_made[self.turn.to_index()] < free_moves {
+            return;
+        }
+
+        match self.turn {
+            Color::White => self.white_time -= time_diff,
+            _ => self.black_time -= time_diff,
+        };
+
+        self.white_time = self.white_time.max(0);
+        self.black_time = self.black_time.max(0);
+    }
+
+    pub fn time_left(&self, color: Color) -> i64 {
+        match color {
+            Color::White => self.white_time,
+            _ => self.black_time,
+        }
+    }
+
+    /// Adds time to a clock without letting it grow past the starting time.
+    pub fn add_time(&mut self, color: Color, time_ms: i64) {
+        let time = match color {
+            Color::White => &mut self.white_time,
+            _ => &mut self.black_time,
+        };
+
+        *time = time.saturating_add(time_ms).min(self.base_ms);
+    }
+
+    /// Adds an earned increment to a clock. Unlike `add_time` it may take the
+    /// clock past the starting time, as Fischer timing does.
+    pub fn add_increment(&mut self, color: Color, time_ms: i64) {
+        let time = match color {
+            Color::White => &mut self.white_time,
+            _ => &mut self.black_time,
+        };
+
+        *time = time.saturating_add(time_ms);
+    }
+
+    /// Only meant for games that haven't started, see `GameSettings`.
+    pub fn set_time_control(&mut self, time_control: TimeControl) {
+        self.white_time = time_control.base_ms;
+        self.black_time = time_control.base_ms;
+        self.base_ms = time_control.base_ms;
+        self.increment_ms = time_control.increment_ms;
+    }
+
+    pub fn time_pressure_count(&self, color: Color) -> u32 {
+        self.time_pressure[color.to_index()]
+    }
+
+    pub fn last_move(&self) -> Option<&LastMove> {
+        self.last_move.as_ref()
+    }
+
+    /// Halves a side's clock before its first move. Returns false if that side
+    /// already moved or went berserk.
+    pub fn berserk(&mut self, color: Color) -> bool {
+        if self.moves_made[color.to_index()] > 0 || self.berserk[color.to_index()] {
+            return false;
+        }
+
+        match color {
+            Color::White => self.white_time /= 2,
+            _ => self.black_time /= 2,
+        };
+
+        self.berserk[color.to_index()] = true;
+
+        true
+    }
+
+    /// Hands the move to the other side. Returns the increment earned by the
+    /// move just made, Fischer and decaying together, which the caller credits
+    /// to the mover or, under the cooperative rule, to the partner.
+    pub fn change_turn(&mut self, last_move: LastMove, increment: &DecayingIncrement) -> i64 {
+        self.moves_made[self.turn.to_index()] += 1;
+
+        if self.time_left(self.turn) < TIME_PRESSURE_MS {
+            self.time_pressure[self.turn.to_index()] += 1;
+        }
+
+        let earned = self.increment_ms + increment.increment_for(self.moves_made[self.turn.to_index()]);
+
+        if last_move.piece == Piece::Pawn || last_move.capture.is_some() {
+            self.halfmove = 0;
+        } else {
+            self.halfmove += 1;
+        }
+
+        if self.turn == Color::Black {
+            self.move_number += 1;
+        }
+
+        self.turn = match self.turn {
+            Color::White => Color::Black,
+            _ => Color::White,
+        };
+
+        self.history.push(last_move.clone());
+        self.last_move = Some(last_move);
+        let _ = self.should_update();
+
+        earned
+    }
+
+    pub fn clock_json(&self) -> Value {
+        json!({
+            "white_ms": self.white_time,
+            "black_ms": self.black_time,
+            "white_time": format_time(self.white_time),
+            "black_time": format_time(self.black_time),
+        })
+    }
+
+    pub fn bank_empty(&self) -> bool {
+        self.white_sp.iter().chain(self.black_sp.iter()).all(|count| *count == 0)
+    }
+
+    /// Replaces the position and banks, keeping the clocks. Used by arbiters
+    /// to correct a board mid-game.
+    pub fn set_position(&mut self, board: Board, banks: ([i32; 5], [i32; 5]), counters: (u32, u32)) {
+        self.board = board;
+        self.halfmove = counters.0;
+        self.move_number = counters.1;
+        self.turn = board.side_to_move();
+        self.white_sp = banks.0;
+        self.black_sp = banks.1;
+        self.restart_history();
+    }
+
+    /// Starts the move history over from the current position, for positions
+    /// set up by arbiters.
+    pub fn restart_history(&mut self) {
+        self.history.clear();
+        self.start_fen = self.fen();
+        self.start_move_number = self.move_number;
+    }
+
+    /// FEN of the board with this game's move counters, which the chess
+    /// crate doesn't keep.
+    pub fn fen(&self) -> String {
+        let fields = self.board.to_string().split_whitespace().take(4).collect::<Vec<&str>>().join(" ");
+
+        format!("{} {} {}", fields, self.halfmove, self.move_number)
+    }
+
+    /// FEN the history starts from, `None` for the usual initial position.
+    pub fn start_fen(&self) -> Option<&str> {
+        (self.start_fen != Board::default().to_string()).then_some(&self.start_fen[..])
+    }
+
+    /// The history as PGN movetext, e.g. `1. e4 e5 2. N@f3`. Promotions are
+    /// followed by a comment naming the partner board the piece came from,
+    /// as the promoted piece is taken from there.
+    pub fn pgn_movetext(&self, partner_board: u8) -> String {
+        let mut tokens = Vec::new();
+        let mut move_number = self.start_move_number;
+
+        for (i, last_move) in self.history.iter().enumerate() {
+            match last_move.color {
+                Color::White => tokens.push(format!("{}.", move_number)),
+                _ if i == 0 => tokens.push(format!("{}...", move_number)),
+                _ => (),
+            };
+
+            tokens.push(last_move.san.clone());
+
+            if let Some(piece) = last_move.promotion {
+                tokens.push(format!("{{{} from board {}}}", piece.to_string(Color::White), partner_board));
+            }
+
+            if last_move.color == Color::Black {
+                move_number += 1;
+            }
+        }
+
+        tokens.join(" ")
+    }
+
+    /// Spare pieces in the crazyhouse FEN style, white in upper case and
+    /// black in lower case, e.g. `QNpp`.
+    pub fn bank_string(&self) -> String {
+        let mut bank = String::new();
+
+        for (sp_array, letters) in [(&self.white_sp, "QRBNP"), (&self.black_sp, "qrbnp")] {
+            for (count, letter) in sp_array.iter().zip(letters.chars()) {
+                for _ in 0..*count {
+                    bank.push(letter);
+                }
+            }
+        }
+
+        bank
+    }
+
+    pub fn add_piece(&mut self, color: &Color, piece: Piece, bank_cap: &[i32; 5]) {
+        let sp_array = match color {
+            Color::Black => &mut self.white_sp,
+            _ => &mut self.black_sp,
+        };
+
+        let i = match piece {
+            Piece::Queen => 0,
+            Piece::Rook => 1,
+            Piece::Bishop => 2,
+            Piece::Knight => 3,
+            Piece::Pawn => 4,
+            _ => return,
+        };
+
+        if sp_array[i] < bank_cap[i] {
+            sp_array[i] += 1;
+        }
+    }
+
+    pub fn add_pawn(&mut self, color: &Color, bank_cap: &[i32; 5]) {
+        let sp_array = match color {
+            Color::White => &mut self.white_sp,
+            _ => &mut self.black_sp,
+        };
+
+        if sp_array[4] < bank_cap[4] {
+            sp_array[4] += 1;
+        }
+    }
+
+    pub fn bank_count(&self, color: Color, piece: Piece) -> i32 {
+        let sp_array = match color {
+            Color::White => &self.white_sp,
+            _ => &self.black_sp,
+        };
+
+        match piece {
+            Piece::Queen => sp_array[0],
+            Piece::Rook => sp_array[1],
+            Piece::Bishop => sp_array[2],
+            Piece::Knight => sp_array[3],
+            Piece::Pawn => sp_array[4],
+            _ => 0,
+        }
+    }
+
+    pub fn decrease_count(&mut self, color: &Color, piece: Piece) -> bool {
+        let sp_array = match color {
+            Color::White => &mut self.white_sp,
+            _ => &mut self.black_sp,
+        };
+
+        let i = match piece {
+            Piece::Queen => 0,
+            Piece::Rook => 1,
+            Piece::Bishop => 2,
+            Piece::Knight => 3,
+            Piece::Pawn => 4,
+            _ => return false,
+        };
+
+        if sp_array[i] <= 0 {
+            return false;
+        }
+
+        sp_array[i] -= 1;
+
+        true
+    }
+}
+
+impl fmt::Display for ChessGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let json = json!({
+            "fen": self.board.to_string(),
+            "side_to_move": if self.turn == Color::White { "w" } else { "b" },
+            "move_number": self.move_number,
+            "halfmove": self.halfmove,
+            "increment_ms": self.increment_ms,
+            "last_move_capture": self.last_move.as_ref().is_some_and(|last_move| last_move.capture.is_some()),
+            "white_sp": self.white_sp,
+            "black_sp": self.black_sp,
+            "white_time": format_time(self.white_time),
+            "black_time": format_time(self.black_time),
+            "white_berserk": self.berserk[Color::White.to_index()],
+            "black_berserk": self.berserk[Color::Black.to_index()],
+            "last_move": self.last_move.as_ref().map(|last_move| last_move.to_string()).unwrap_or_default(),
+            "history": self.history.iter().map(LastMove::to_json).collect::<Vec<Value>>(),
+        });
+
+        write!(f, "{}", json)
+    }
+}
+
+fn format_time(time_ms: i64) -> String {
+    let seconds = (time_ms + 999) / 1000;
+
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Inverse of `bank_string`, returns the white and black banks.
+pub fn parse_bank_string(bank: &str) -> Option<([i32; 5], [i32; 5])> {
+    let mut white_sp = [0; 5];
+    let mut black_sp = [0; 5];
+
+    for letter in bank.chars() {
+        let sp_array = if letter.is_ascii_uppercase() { &mut white_sp } else { &mut black_sp };
+
+        let i = match letter.to_ascii_uppercase() {
+            'Q' => 0,
+            'R' => 1,
+            'B' => 2,
+            'N' => 3,
+            'P' => 4,
+            _ => return None,
+        };
+
+        sp_array[i] += 1;
+    }
+
+    Some((white_sp, black_sp))
+}
+
+/// Piece names as the web client uses them, e.g. `wN` or `bP`.
+fn piece_name(piece: Piece, color: Color) -> String {
+    let color = match color {
+        Color::White => 'w',
+        _ => 'b',
+    };
+
+    format!("{}{}", color, piece.to_string(Color::White))
+}
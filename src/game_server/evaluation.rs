@@ -0,0 +1,117 @@
+use chess::{Board, Color, Piece, ALL_SQUARES};
+
+use crate::game_server::chess_game::ChessGame;
+
+/// Centipawn values in bank order (queen, rook, bishop, knight, pawn), used
+/// unless the game configures its own.
+pub static DEFAULT_PIECE_VALUES: [i32; 5] = [900, 500, 330, 320, 100];
+
+/// Piece-square tables from white's point of view, a1 first. Black squares
+/// are looked up with the rank mirrored.
+static PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5,  5, 10, 25, 25, 10,  5,  5,
+    10, 10, 20, 30, 30, 20, 10, 10,
+    50, 50, 50, 50, 50, 50, 50, 50,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+static KNIGHT_TABLE: [i32; 64] = [
+   -50,-40,-30,-30,-30,-30,-40,-50,
+   -40,-20,  0,  5,  5,  0,-20,-40,
+   -30,  5, 10, 15, 15, 10,  5,-30,
+   -30,  0, 15, 20, 20, 15,  0,-30,
+   -30,  5, 15, 20, 20, 15,  5,-30,
+   -30,  0, 10, 15, 15, 10,  0,-30,
+   -40,-20,  0,  0,  0,  0,-20,-40,
+   -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+static CENTER_TABLE: [i32; 64] = [
+   -20,-10,-10,-10,-10,-10,-10,-20,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -10,  0,  5, 10, 10,  5,  0,-10,
+   -10,  5, 10, 10, 10, 10,  5,-10,
+   -10,  5, 10, 10, 10, 10,  5,-10,
+   -10,  0,  5, 10, 10,  5,  0,-10,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+/// Shallow static evaluation of one board in centipawns, positive when white
+/// is better. Counts material on the board and in the banks plus a few
+/// piece-square terms; it doesn't search, so tactics are invisible to it.
+pub fn evaluate(game: &ChessGame, piece_values: &[i32; 5]) -> i32 {
+    let board_score = evaluate_board(&game.board, piece_values);
+    let bank_score = bank_value(&game.white_sp, piece_values) - bank_value(&game.black_sp, piece_values);
+
+    board_score + bank_score
+}
+
+/// Material only, on the board and in the banks, positive when white has
+/// more.
+pub fn evaluate_material(game: &ChessGame, piece_values: &[i32; 5]) -> i32 {
+    let mut score = bank_value(&game.white_sp, piece_values) - bank_value(&game.black_sp, piece_values);
+
+    for square in game.board.combined().into_iter() {
+        if let (Some(piece), Some(color)) = (game.board.piece_on(square), game.board.color_on(square)) {
+            let value = piece_value(piece, piece_values);
+
+            score += if color == Color::White { value } else { -value };
+        }
+    }
+
+    score
+}
+
+/// Value of a single piece, the one place material is priced. Kings are
+/// never traded, so they're worth nothing here.
+pub fn piece_value(piece: Piece, piece_values: &[i32; 5]) -> i32 {
+    match piece {
+        Piece::Queen => piece_values[0],
+        Piece::Rook => piece_values[1],
+        Piece::Bishop => piece_values[2],
+        Piece::Knight => piece_values[3],
+        Piece::Pawn => piece_values[4],
+        Piece::King => 0,
+    }
+}
+
+fn evaluate_board(board: &Board, piece_values: &[i32; 5]) -> i32 {
+    let mut score = 0;
+
+    for square in ALL_SQUARES {
+        let (piece, color) = match (board.piece_on(square), board.color_on(square)) {
+            (Some(piece), Some(color)) => (piece, color),
+            _ => continue,
+        };
+
+        let index = match color {
+            Color::White => square.to_index(),
+            _ => square.to_index() ^ 56,
+        };
+
+        let position = match piece {
+            Piece::Pawn => PAWN_TABLE[index],
+            Piece::Knight => KNIGHT_TABLE[index],
+            Piece::Bishop => CENTER_TABLE[index],
+            Piece::Queen => CENTER_TABLE[index] / 2,
+            _ => 0,
+        };
+        let value = piece_value(piece, piece_values) + position;
+
+        score += match color {
+            Color::White => value,
+            _ => -value,
+        };
+    }
+
+    score
+}
+
+fn bank_value(sp_array: &[i32; 5], piece_values: &[i32; 5]) -> i32 {
+    sp_array.iter().zip(piece_values.iter()).map(|(count, value)| count * value).sum()
+}
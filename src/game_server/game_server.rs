@@ -1,115 +1,726 @@
-use std::{
-    thread,
-    sync::{Arc, RwLock},
-    collections::HashMap,
-    net::TcpListener,
-    time::Duration,
-};
-
-use tungstenite::{
-    accept,
-    protocol::{Role, WebSocket},
-    Message,
-};
-
-use crate::game_server::message_queue::MessageQueue;
-use crate::game_server::tandem_game::{TandemGameInterface, TandemMove};
-
-
-pub fn start_server() {
-    thread::spawn(|| {
-        let server = TcpListener::bind("0.0.0.0:9091").unwrap();
-        let board_og = TandemGameInterface::new();
-        let client_map = Arc::new(RwLock::new(HashMap::<usize, MessageQueue<String>>::new()));
-        let client_sync_map = client_map.clone();
-        let tandem_sync = board_og.clone();
-        let mut i = 0;
-
-        thread::spawn(move || {
-            let mut ping_cnt = 0;
-    
-            loop {
-                if tandem_sync.should_update() || ping_cnt >= 100 {
-                    for client in client_sync_map.read().unwrap().values() {
-                        client.produce(tandem_sync.get_fen(true));
-                    }
-
-                    ping_cnt = 0;
-                }
-
-                thread::sleep(Duration::from_millis(50));
-                ping_cnt += 1;
-            }
-        });
-
-        for stream in server.incoming() {
-            let board = board_og.clone();
-            let id = i;
-            let client_map_c = client_map.clone();
-            i += 1;
-
-            thread::spawn(move || {
-                let stream_read = stream.unwrap();
-                let send_stream = stream_read.try_clone().unwrap();
-
-                let mut websocket_read = match accept(stream_read) {
-                    Ok(v) => v,
-                    Err(_) => return,
-                };
-                let msg_queue = MessageQueue::<String>::new();
-                let msg_queue_c = msg_queue.clone();
-                let mut websocket_send = WebSocket::from_raw_socket(send_stream, Role::Server, None);
-
-                thread::spawn(move || {
-                    loop {
-                        let msg = msg_queue_c.consume_blocking();
-
-                        match websocket_send.send(Message::Text(msg.into())) {
-                            Ok(_) => (),
-                            Err(_) => break, 
-                        };
-                    }
-                });
-
-                msg_queue.produce(board.get_fen(true));
-                client_map_c.write().unwrap().insert(id, msg_queue.clone());
-
-                loop {
-                    let msg:String = match websocket_read.read() {
-                        Ok(message) => match message {
-                            msg @ Message::Text(_) => msg.to_string(),
-                            _msg @ Message::Ping(_) | _msg @ Message::Pong(_) => continue,
-                            _ => break,
-                        },
-                        Err(_) => break,
-                    };
-
-                    if msg == "Reset Game" {
-                        board.reset();
-
-                        for client in client_map_c.read().unwrap().values() {
-                            client.produce(board.get_fen(true));
-                        }
-
-                        continue;
-                    }
-
-                    let tandem_move = match TandemMove::from_string(msg) {
-                        Some(v) => v,
-                        None => continue,
-                    };
-
-                    let changed = board.move_piece(&tandem_move);
-
-                    if changed {
-                        for client in client_map_c.read().unwrap().values() {
-                            client.produce(board.get_fen(true));
-                        }
-                    } else {
-                        msg_queue.produce(board.get_fen(false));
-                    }
-                }
-            });
-        }
-    });
-}
\ No newline at end of file
+use std::{
+    thread,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, RwLock},
+    collections::HashMap,
+    env,
+    io::{self, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use tungstenite::{
+    accept_hdr,
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    protocol::{Role, WebSocket},
+    Message,
+};
+
+use serde_json::{json, Value};
+use chrono::Utc;
+use chess::Color;
+
+use crate::game_server::message_queue::MessageQueue;
+use crate::game_server::settings::TimeControl;
+use crate::game_server::protocol::{self, Command, PROTOCOL_VERSION, ProtocolError};
+use crate::game_server::tandem_game::TandemGameInterface;
+
+pub type ClientMap = Arc<RwLock<HashMap<u64, MessageQueue<String>>>>;
+pub type RoomMap = Arc<RwLock<HashMap<String, Room>>>;
+
+/// Room joined by clients that connect without naming one. It is never torn
+/// down, and HTTP endpoints without a room segment follow it.
+pub static DEFAULT_ROOM: &str = "main";
+static MAX_ROOM_ID_LENGTH: usize = 32;
+
+/// An independent tandem game and the clients connected to it.
+#[derive(Clone)]
+pub struct Room {
+    pub game: TandemGameInterface,
+    pub clients: ClientMap,
+}
+
+impl Room {
+    fn new(game: TandemGameInterface) -> Self {
+        Room {
+            game,
+            clients: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+/// Room named by the websocket path, e.g. `/blitz-1`. The bare `/` is the
+/// default room, anything that isn't a single short segment of letters,
+/// digits, `-` or `_` is refused.
+fn room_from_path(path: &str) -> Option<String> {
+    let room_id = path.strip_prefix('/')?;
+
+    if room_id.is_empty() {
+        return Some(DEFAULT_ROOM.to_owned());
+    }
+
+    let valid = room_id.len() <= MAX_ROOM_ID_LENGTH
+        && room_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    valid.then(|| room_id.to_owned())
+}
+
+/// Time control asked for in the websocket query, e.g. `?tc=3+2`. `Err` if
+/// it is there but can't be parsed.
+fn time_control_from_query(query: Option<&str>) -> Result<Option<TimeControl>, ()> {
+    let value = query.into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("tc="));
+
+    match value {
+        // A literal `+` in a query usually arrives encoded.
+        Some(v) => TimeControl::parse(&v.replace("%2B", "+").replace("%2b", "+")).map(Some).ok_or(()),
+        None => Ok(None),
+    }
+}
+
+/// Adds a client to a room, creating the room on first join with the given
+/// time control. Joining an existing room keeps its time control. The client
+/// gets the room's snapshot before anything broadcast after it joined.
+fn join_room(rooms: &RoomMap, room_id: &str, time_control: TimeControl, id: u64, msg_queue: &MessageQueue<String>) -> Room {
+    let mut rooms = rooms.write().unwrap();
+    let default_game = rooms[DEFAULT_ROOM].game.clone();
+    let room = rooms.entry(room_id.to_owned())
+        .or_insert_with(|| {
+            let room = Room::new(default_game.sibling(time_control));
+            start_replica(room_id, &room.clients);
+
+            room
+        })
+        .clone();
+
+    msg_queue.produce(room.game.get_snapshot(false));
+    room.clients.write().unwrap().insert(id, msg_queue.clone());
+
+    room
+}
+
+/// Queue counters of every connected client, keyed by connection id.
+pub fn connection_stats(client_map: &ClientMap) -> Value {
+    let mut stats = serde_json::Map::new();
+
+    for (id, msg_queue) in client_map.read().unwrap().iter() {
+        let queue_stats = msg_queue.stats();
+
+        stats.insert(id.to_string(), json!({
+            "enqueued": queue_stats.enqueued,
+            "sent": queue_stats.sent,
+            "dropped": queue_stats.dropped,
+            "skipped": queue_stats.skipped,
+            "queue_depth": queue_stats.depth,
+            "wedged": msg_queue.is_full(),
+        }));
+    }
+
+    Value::Object(stats)
+}
+
+/// Owns a connection's entry in the client map and removes it, closing its
+/// queue, when the connection handler returns by any path. The last client
+/// out of a room other than the default one tears the room down.
+struct ClientGuard {
+    id: u64,
+    room_id: String,
+    rooms: RoomMap,
+    client_map: ClientMap,
+    board: TandemGameInterface,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.board.log_event("disconnect", json!({ "id": self.id }));
+
+        // Everyone else learns the seat is free again.
+        if self.board.stand(self.id) {
+            broadcast(&self.client_map, self.board.get_fen(true));
+        }
+
+        // Held across the removal so nobody joins the room while it is
+        // being torn down.
+        let mut rooms = match self.rooms.write() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+
+        let mut client_map = match self.client_map.write() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+
+        if let Some(msg_queue) = client_map.remove(&self.id) {
+            msg_queue.close();
+        }
+
+        // The replica sink doesn't keep a room alive, it goes with it.
+        if client_map.keys().all(|id| *id == REPLICA_ID) && self.room_id != DEFAULT_ROOM {
+            for msg_queue in client_map.drain().map(|(_, msg_queue)| msg_queue) {
+                msg_queue.close();
+            }
+
+            drop(client_map);
+            rooms.remove(&self.room_id);
+        }
+    }
+}
+
+/// Pushes the same serialized frame to every connected client. Clients whose
+/// queue is already full are skipped rather than churned; they show up as
+/// wedged in the connection stats.
+pub fn broadcast(client_map: &ClientMap, message: String) {
+    for client in client_map.read().unwrap().values() {
+        let _ = client.try_produce(message.clone());
+    }
+}
+
+static BIND_ATTEMPTS: u32 = 5;
+
+/// Hands out the next connection id. The counter wraps instead of
+/// overflowing, and ids still held by a live connection in any room are
+/// skipped so two clients never share a key. `REPLICA_ID` is never handed
+/// out, even in a room without a replica.
+fn next_client_id(counter: &mut u64, rooms: &RoomMap) -> u64 {
+    let rooms = rooms.read().unwrap();
+
+    loop {
+        let id = *counter;
+        *counter = counter.wrapping_add(1);
+
+        if id == REPLICA_ID {
+            continue;
+        }
+
+        if !rooms.values().any(|room| room.clients.read().unwrap().contains_key(&id)) {
+            return id;
+        }
+    }
+}
+
+/// Admin commands are disabled unless `TANDEM_ADMIN_SECRET` is set.
+pub fn admin_secret_matches(secret: &str) -> bool {
+    match env::var("TANDEM_ADMIN_SECRET") {
+        Ok(v) => !v.is_empty() && v == secret,
+        Err(_) => false,
+    }
+}
+
+/// Binds the websocket listener, retrying with exponential backoff so a quick
+/// restart doesn't fail while the old socket is still being released.
+fn bind_with_retry(addr: &str) -> io::Result<TcpListener> {
+    let mut backoff = Duration::from_millis(250);
+    let mut attempt = 1;
+
+    loop {
+        match TcpListener::bind(addr) {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt < BIND_ATTEMPTS => {
+                println!("Failed to bind {} (attempt {}/{}): {:?}", addr, attempt, BIND_ATTEMPTS, e);
+
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Client map key of the replica sink, skipped when handing out connection
+/// ids.
+static REPLICA_ID: u64 = u64::MAX;
+
+/// Mirrors every broadcast frame of a room, newline separated, to the TCP
+/// address in `TANDEM_REPLICA_ADDR` so a separate process can serve
+/// spectators. Each room gets its own connection, opened with a
+/// `{"type":"replica","room":...}` line. The sink sits in the client map like
+/// any connection, so it gets exactly the frames clients get and in the same
+/// order. Lost connections are retried until the room is torn down.
+fn start_replica(room_id: &str, client_map: &ClientMap) {
+    let addr = match env::var("TANDEM_REPLICA_ADDR") {
+        Ok(v) if !v.is_empty() => v,
+        _ => return,
+    };
+
+    let msg_queue = MessageQueue::<String>::new();
+    client_map.write().unwrap().insert(REPLICA_ID, msg_queue.clone());
+
+    let hello = json!({ "type": "replica", "room": room_id }).to_string();
+
+    thread::spawn(move || {
+        while !msg_queue.is_closed() {
+            let mut stream = match TcpStream::connect(&addr) {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("Failed to connect to replica {}: {:?}", addr, e);
+                    thread::sleep(Duration::from_secs(1));
+
+                    continue;
+                },
+            };
+
+            if stream.write_all(hello.as_bytes()).and_then(|_| stream.write_all(b"\n")).is_err() {
+                continue;
+            }
+
+            while let Some(msg) = msg_queue.consume_blocking() {
+                if stream.write_all(msg.as_bytes()).and_then(|_| stream.write_all(b"\n")).is_err() {
+                    break;
+                }
+
+                msg_queue.mark_sent();
+            }
+        }
+    });
+}
+
+pub fn start_server(addr: &str) -> io::Result<RoomMap> {
+    let server = bind_with_retry(addr)?;
+    let default_room = Room::new(TandemGameInterface::new(TimeControl::default_control()));
+    let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+    let rooms_http = rooms.clone();
+
+    start_replica(DEFAULT_ROOM, &default_room.clients);
+    rooms.write().unwrap().insert(DEFAULT_ROOM.to_owned(), default_room);
+
+    thread::spawn(move || {
+        let rooms_sync = rooms.clone();
+        let mut id_counter: u64 = 0;
+
+        thread::spawn(move || {
+            let mut ping_cnt = 0;
+    
+            loop {
+                let heartbeat = ping_cnt >= 100;
+                let room_list = rooms_sync.read().unwrap().values().cloned().collect::<Vec<Room>>();
+
+                // Keeps the clocks synchronized even with nobody connected, but
+                // only builds clock frames when someone is there to get them.
+                for room in room_list {
+                    let was_finished = room.game.is_finished();
+                    let should_update = room.game.should_update();
+                    let has_clients = !room.clients.read().unwrap().is_empty();
+
+                    if has_clients && (should_update || heartbeat) {
+                        broadcast(&room.clients, room.game.get_clock());
+                    }
+
+                    // A flag fall ends the game here rather than in a move,
+                    // so the result has to be sent out from here too.
+                    if has_clients && !was_finished && room.game.is_finished() {
+                        broadcast(&room.clients, room.game.get_fen(true));
+                    }
+                }
+
+                if heartbeat {
+                    ping_cnt = 0;
+                }
+
+                thread::sleep(Duration::from_millis(50));
+                ping_cnt += 1;
+            }
+        });
+
+        for stream in server.incoming() {
+            let id = next_client_id(&mut id_counter, &rooms);
+            let rooms_c = rooms.clone();
+
+            thread::spawn(move || {
+                let stream_read = match stream {
+                    Ok(v) => v,
+                    Err(e) => {
+                        println!("Failed to accept client {}: {:?}", id, e);
+                        return;
+                    },
+                };
+
+                // The sender thread needs its own handle. Without one (e.g. out
+                // of file descriptors) the connection is dropped, which closes it.
+                let send_stream = match stream_read.try_clone() {
+                    Ok(v) => v,
+                    Err(e) => {
+                        println!("Failed to clone the stream of client {}: {:?}", id, e);
+                        let _ = stream_read.shutdown(Shutdown::Both);
+                        return;
+                    },
+                };
+
+                let mut room_id = None;
+                let mut time_control = None;
+                // The error type is dictated by tungstenite's handshake callback.
+                #[allow(clippy::result_large_err)]
+                let pick_room = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+                    room_id = room_from_path(request.uri().path());
+
+                    let status = match (&room_id, time_control_from_query(request.uri().query())) {
+                        (None, _) => StatusCode::NOT_FOUND,
+                        (_, Err(_)) => StatusCode::BAD_REQUEST,
+                        (Some(_), Ok(v)) => {
+                            time_control = v;
+
+                            return Ok(response);
+                        },
+                    };
+
+                    let mut response = ErrorResponse::new(None);
+                    *response.status_mut() = status;
+
+                    Err(response)
+                };
+
+                let mut websocket_read = match accept_hdr(stream_read, pick_room) {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+                let room_id = room_id.unwrap_or_else(|| DEFAULT_ROOM.to_owned());
+                let msg_queue = MessageQueue::<String>::new();
+                let msg_queue_c = msg_queue.clone();
+                let mut websocket_send = WebSocket::from_raw_socket(send_stream, Role::Server, None);
+
+                let time_control = time_control.unwrap_or_else(TimeControl::default_control);
+                let room = join_room(&rooms_c, &room_id, time_control, id, &msg_queue);
+                let board = room.game;
+                let client_map_c = room.clients;
+                let client_map_s = client_map_c.clone();
+
+                let _client_guard = ClientGuard {
+                    id,
+                    room_id: room_id.clone(),
+                    rooms: rooms_c.clone(),
+                    client_map: client_map_c.clone(),
+                    board: board.clone(),
+                };
+
+                board.log_event("connect", json!({ "id": id, "room": room_id }));
+
+                let mut is_admin = false;
+                // Board and color this client plays, spectators have none.
+                let mut seat: Option<(u8, Color)> = None;
+                // The deprecated semicolon format is logged once per client.
+                let mut warned_legacy = false;
+
+                thread::spawn(move || {
+                    // Ok(true) means the queue was closed by the client guard,
+                    // which already took the client out of the map.
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        while let Some(msg) = msg_queue_c.consume_blocking() {
+                            match websocket_send.send(Message::Text(msg.into())) {
+                                Ok(_) => msg_queue_c.mark_sent(),
+                                Err(_) => return false, 
+                            };
+                        }
+
+                        true
+                    }));
+
+                    // A dead sender would leave the client in the map without
+                    // ever receiving updates again, so drop it and close the
+                    // socket. Shutting the socket down also wakes the reader,
+                    // which may otherwise block on a half-open connection.
+                    if !matches!(result, Ok(true)) {
+                        match result {
+                            Err(_) => println!("Sender for client {} panicked, removing it", id),
+                            _ => println!("Sending to client {} failed, removing it", id),
+                        };
+
+                        client_map_s.write().unwrap().remove(&id);
+                        msg_queue_c.close();
+
+                        let _ = websocket_send.close(None);
+                        let _ = websocket_send.flush();
+                        let _ = websocket_send.get_ref().shutdown(Shutdown::Both);
+                    }
+                });
+
+                loop {
+                    let msg:String = match websocket_read.read() {
+                        Ok(message) => match message {
+                            msg @ Message::Text(_) => msg.to_string(),
+                            Message::Close(_) => {
+                                // tungstenite queues the close reply, flushing sends it.
+                                let _ = websocket_read.flush();
+                                break;
+                            },
+                            Message::Ping(_) | Message::Pong(_)
+                            | Message::Binary(_) | Message::Frame(_) => continue,
+                        },
+                        Err(_) => break,
+                    };
+
+                    let command = match protocol::parse(&msg) {
+                        Ok((command, legacy)) => {
+                            if legacy && !warned_legacy {
+                                warned_legacy = true;
+                                board.log_event("legacy_protocol", json!({ "id": id }));
+                            }
+
+                            command
+                        },
+                        // Legacy clients never got an answer to unknown
+                        // strings and might not expect one.
+                        Err(ProtocolError::Unknown) => continue,
+                        Err(e) => {
+                            msg_queue.produce(json!({
+                                "type": "protocol_error",
+                                "code": e.code(),
+                                "detail": match e {
+                                    ProtocolError::Malformed(detail) => detail,
+                                    ProtocolError::UnsupportedVersion(v) => format!("expected version {}, got {}", PROTOCOL_VERSION, v),
+                                    ProtocolError::Unknown => String::new(),
+                                },
+                            }).to_string());
+
+                            continue;
+                        },
+                    };
+
+                    // Commands a player sends for their own seat are only
+                    // taken from the player in that seat, spectators are
+                    // ignored.
+                    let requested_seat = match &command {
+                        Command::Resign { board, color }
+                        | Command::OfferDraw { board, color }
+                        | Command::AcceptDraw { board, color }
+                        | Command::Berserk { board, color }
+                        | Command::Move { board, color, .. }
+                        | Command::Drop { board, color, .. } => Some((*board, color.color())),
+                        _ => None,
+                    };
+
+                    if requested_seat.is_some() && seat.is_none() {
+                        continue;
+                    }
+
+                    let not_your_seat = requested_seat.is_some() && requested_seat != seat;
+
+                    // Commands for the whole game need a seat at the table,
+                    // or admin rights.
+                    let game_wide = matches!(command, Command::Reset | Command::Abort | Command::Setting { .. });
+
+                    if game_wide && seat.is_none() && !is_admin {
+                        msg_queue.produce(board.get_error("not_seated"));
+
+                        continue;
+                    }
+
+                    match command {
+                        Command::Reset => {
+                            board.reset();
+
+                            broadcast(&client_map_c, board.get_snapshot(true));
+                        },
+                        Command::Abort => {
+                            if board.abort() {
+                                broadcast(&client_map_c, board.get_fen(true));
+                            } else {
+                                msg_queue.produce(board.get_fen(false));
+                            }
+                        },
+                        Command::Setting { key, value } => {
+                            if board.change_setting(&key, &value) {
+                                broadcast(&client_map_c, board.get_fen(true));
+                            } else {
+                                msg_queue.produce(board.get_fen(false));
+                            }
+                        },
+                        Command::Admin { secret } => {
+                            is_admin = admin_secret_matches(&secret);
+                            msg_queue.produce(json!({ "type": "admin", "granted": is_admin }).to_string());
+                        },
+                        Command::SetFen { board: b, fen, banks } => {
+                            let result = match is_admin {
+                                true => board.set_position(&b, &fen, &banks),
+                                false => Err("not_authorized"),
+                            };
+
+                            match result {
+                                Ok(_) => broadcast(&client_map_c, board.get_snapshot(true)),
+                                Err(reason) => msg_queue.produce(board.get_error(reason)),
+                            };
+                        },
+                        // Application level round trip, answered through the
+                        // queue so it includes any queueing delay.
+                        Command::PingApp { nonce } => {
+                            msg_queue.produce(format!("pong_app;{};{}", nonce, Utc::now().timestamp_millis()));
+                        },
+                        Command::SetResult { outcome, reason } => {
+                            let result = match is_admin {
+                                true => board.set_result(&outcome, &reason),
+                                false => Err("not_authorized"),
+                            };
+
+                            match result {
+                                Ok(_) => broadcast(&client_map_c, board.get_fen(true)),
+                                Err(reason) => msg_queue.produce(board.get_error(reason)),
+                            };
+                        },
+                        Command::Setup => {
+                            if is_admin && board.start_setup() {
+                                broadcast(&client_map_c, board.get_fen(true));
+                            } else {
+                                msg_queue.produce(board.get_fen(false));
+                            }
+                        },
+                        Command::Place { board: b, square, piece } => {
+                            let result = match is_admin {
+                                true => board.place(&b, &square, &piece),
+                                false => Err("not_authorized"),
+                            };
+
+                            match result {
+                                Ok(_) => broadcast(&client_map_c, board.get_fen(true)),
+                                Err(reason) => msg_queue.produce(board.get_error(reason)),
+                            };
+                        },
+                        Command::Lock => {
+                            let result = match is_admin {
+                                true => board.lock(),
+                                false => Err("not_authorized"),
+                            };
+
+                            match result {
+                                Ok(_) => broadcast(&client_map_c, board.get_fen(true)),
+                                Err(reason) => msg_queue.produce(board.get_error(reason)),
+                            };
+                        },
+                        Command::Slow { moves_per_second } => {
+                            match is_admin {
+                                true => {
+                                    board.set_slow_mode(moves_per_second);
+                                    msg_queue.produce(json!({ "type": "slow_mode", "moves_per_second": moves_per_second }).to_string());
+                                },
+                                false => msg_queue.produce(board.get_error("not_authorized")),
+                            };
+                        },
+                        Command::Sit { board: b, color } => {
+                            let result = match b {
+                                1 | 2 => board.sit(id, b, color.color()),
+                                _ => Err("invalid_command"),
+                            };
+
+                            match result {
+                                Ok(_) => {
+                                    seat = Some((b, color.color()));
+                                    broadcast(&client_map_c, board.get_fen(true));
+                                },
+                                Err(reason) => msg_queue.produce(board.get_error(reason)),
+                            };
+                        },
+                        Command::Stand => {
+                            seat = None;
+
+                            if board.stand(id) {
+                                broadcast(&client_map_c, board.get_fen(true));
+                            }
+                        },
+                        Command::Title { title } => {
+                            if is_admin && board.set_title(&title) {
+                                broadcast(&client_map_c, board.get_fen(true));
+                            } else {
+                                msg_queue.produce(board.get_fen(false));
+                            }
+                        },
+                        Command::LastMove { board: b @ (1 | 2) } => msg_queue.produce(board.last_move(b)),
+                        Command::San { board: b @ (1 | 2) } => msg_queue.produce(board.last_move_san(b)),
+                        Command::LastMove { .. } | Command::San { .. } => (),
+                        Command::Resign { .. } | Command::OfferDraw { .. } | Command::AcceptDraw { .. } => {
+                            let result = match (not_your_seat, &command) {
+                                (true, _) => Err("not_your_seat"),
+                                (_, Command::Resign { board: b, color }) => board.resign(*b, color.color()),
+                                (_, Command::OfferDraw { board: b, color }) => board.offer_draw(*b, color.color()),
+                                (_, Command::AcceptDraw { board: b, color }) => board.accept_draw(*b, color.color()),
+                                _ => Err("invalid_command"),
+                            };
+
+                            match result {
+                                Ok(_) => broadcast(&client_map_c, board.get_fen(true)),
+                                Err(reason) => msg_queue.produce(board.get_error(reason)),
+                            };
+                        },
+                        Command::Berserk { .. } if not_your_seat => msg_queue.produce(board.get_error("not_your_seat")),
+                        Command::Berserk { board: b, color } => {
+                            if (b == 1 || b == 2) && board.berserk(b, color.color()) {
+                                broadcast(&client_map_c, board.get_fen(true));
+                            } else {
+                                msg_queue.produce(board.get_fen(false));
+                            }
+                        },
+                        Command::Clock { board: b, color } => {
+                            if b == 1 || b == 2 {
+                                msg_queue.produce(board.time_left(b, color.color()));
+                            }
+                        },
+                        Command::Move { .. } | Command::Drop { .. } => {
+                            let tandem_move = match command.to_tandem_move() {
+                                Some(v) => v,
+                                None => {
+                                    msg_queue.produce(board.get_error("invalid_board"));
+
+                                    continue;
+                                },
+                            };
+
+                            if not_your_seat {
+                                msg_queue.produce(board.get_rejection(&tandem_move, "not_your_seat"));
+
+                                continue;
+                            }
+
+                            match board.move_piece(&tandem_move) {
+                                Ok(_) => {
+                                    broadcast(&client_map_c, board.get_fen(true));
+
+                                    if let Some(warning) = board.mate_warning(&tandem_move) {
+                                        broadcast(&client_map_c, warning);
+                                    }
+                                },
+                                Err(reason) => msg_queue.produce(board.get_rejection(&tandem_move, reason)),
+                            };
+                        },
+                    };
+                }
+            });
+        }
+    });
+
+    Ok(rooms_http)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rooms_with_clients(ids: &[u64]) -> RoomMap {
+        let room = Room::new(TandemGameInterface::new(TimeControl::default_control()));
+
+        for id in ids {
+            room.clients.write().unwrap().insert(*id, MessageQueue::new());
+        }
+
+        Arc::new(RwLock::new(HashMap::from([(DEFAULT_ROOM.to_owned(), room)])))
+    }
+
+    #[test]
+    fn client_ids_skip_live_connections() {
+        let rooms = rooms_with_clients(&[1, 2, 4]);
+        let mut counter = 1;
+
+        assert_eq!(next_client_id(&mut counter, &rooms), 3);
+        assert_eq!(next_client_id(&mut counter, &rooms), 5);
+        assert_eq!(counter, 6);
+    }
+
+    #[test]
+    fn client_ids_wrap_around_the_replica_id() {
+        let rooms = rooms_with_clients(&[0]);
+        let mut counter = REPLICA_ID - 1;
+
+        assert_eq!(next_client_id(&mut counter, &rooms), REPLICA_ID - 1);
+        assert_eq!(next_client_id(&mut counter, &rooms), 1);
+        assert_eq!(counter, 2);
+    }
+}
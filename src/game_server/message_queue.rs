@@ -1,51 +1,193 @@
-use std::{
-    sync::{Arc, RwLock},
-    collections::VecDeque,
-    thread,
-    time::Duration,
-};
-
-pub struct MessageQueue<T> {
-    message_queue: Arc<RwLock<VecDeque<T>>>,
-}
-
-impl<T> MessageQueue<T> {
-    pub fn new() -> Self {
-        MessageQueue { message_queue: Arc::new(RwLock::new(VecDeque::new())) }
-    }
-
-    pub fn produce(&self, message: T) {
-        let mut message_queue = self.message_queue.write().unwrap();
-
-        if message_queue.len() > 1_000 {
-            let _ = message_queue.pop_front();
-        }
-
-        message_queue.push_back(message);
-    }
-
-    pub fn consume(&self) -> Option<T> {
-        self.message_queue.write().unwrap().pop_front()
-    }
-
-    pub fn consume_blocking(&self) -> T {
-        loop {
-            let msg = self.consume();
-
-            let message = match msg {
-                Some(v) => v,
-                None => {
-                    thread::sleep(Duration::from_millis(20));
-
-                    continue;
-                }
-            };
-
-            return message;
-        }
-    }
-
-    pub fn clone(&self) -> Self {
-        MessageQueue { message_queue: self.message_queue.clone() }
-    }
-}
+use std::{
+    sync::{Arc, Condvar, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}},
+    collections::VecDeque,
+};
+
+static MAX_DEPTH: usize = 1_000;
+
+/// Point-in-time counters of a queue, for spotting slow consumers.
+#[derive(Clone, Copy, Debug)]
+pub struct QueueStats {
+    pub enqueued: u64,
+    pub sent: u64,
+    pub dropped: u64,
+    pub skipped: u64,
+    pub depth: usize,
+}
+
+#[derive(Default)]
+struct QueueCounters {
+    enqueued: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    skipped: AtomicU64,
+}
+
+pub struct MessageQueue<T> {
+    message_queue: Arc<Mutex<VecDeque<T>>>,
+    /// Signalled on every produce and on close, so consumers park instead of
+    /// polling.
+    available: Arc<Condvar>,
+    closed: Arc<AtomicBool>,
+    counters: Arc<QueueCounters>,
+}
+
+impl<T> MessageQueue<T> {
+    pub fn new() -> Self {
+        MessageQueue {
+            message_queue: Arc::new(Mutex::new(VecDeque::new())),
+            available: Arc::new(Condvar::new()),
+            closed: Arc::new(AtomicBool::new(false)),
+            counters: Arc::new(QueueCounters::default()),
+        }
+    }
+
+    pub fn produce(&self, message: T) {
+        if self.is_closed() {
+            return;
+        }
+
+        let mut message_queue = self.message_queue.lock().unwrap();
+
+        if message_queue.len() > MAX_DEPTH {
+            let _ = message_queue.pop_front();
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        message_queue.push_back(message);
+        self.counters.enqueued.fetch_add(1, Ordering::Relaxed);
+        self.available.notify_one();
+    }
+
+    /// Like `produce`, but leaves a full queue alone instead of churning it,
+    /// counting the message as skipped. Returns whether it was queued.
+    pub fn try_produce(&self, message: T) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+
+        let mut message_queue = self.message_queue.lock().unwrap();
+
+        if message_queue.len() > MAX_DEPTH {
+            self.counters.skipped.fetch_add(1, Ordering::Relaxed);
+
+            return false;
+        }
+
+        message_queue.push_back(message);
+        self.counters.enqueued.fetch_add(1, Ordering::Relaxed);
+        self.available.notify_one();
+
+        true
+    }
+
+    /// A consumer whose queue is at the cap isn't keeping up at all.
+    pub fn is_full(&self) -> bool {
+        self.message_queue.lock().unwrap().len() > MAX_DEPTH
+    }
+
+    /// Called by the consumer once a message actually went out.
+    pub fn mark_sent(&self) {
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            enqueued: self.counters.enqueued.load(Ordering::Relaxed),
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            skipped: self.counters.skipped.load(Ordering::Relaxed),
+            depth: self.message_queue.lock().unwrap().len(),
+        }
+    }
+
+    /// Non-blocking counterpart of `consume_blocking`.
+    #[allow(dead_code)]
+    pub fn consume(&self) -> Option<T> {
+        self.message_queue.lock().unwrap().pop_front()
+    }
+
+    /// Waits for the next message. Returns None once the queue is closed.
+    pub fn consume_blocking(&self) -> Option<T> {
+        let mut message_queue = self.message_queue.lock().unwrap();
+
+        loop {
+            if self.is_closed() {
+                return None;
+            }
+
+            if let Some(message) = message_queue.pop_front() {
+                return Some(message);
+            }
+
+            message_queue = self.available.wait(message_queue).unwrap();
+        }
+    }
+
+    /// Closes the queue for good, dropping pending messages and waking up
+    /// consumers.
+    pub fn close(&self) {
+        // Flagged under the lock so a consumer can't miss the wakeup between
+        // checking the flag and parking.
+        let mut message_queue = self.message_queue.lock().unwrap();
+
+        self.closed.store(true, Ordering::SeqCst);
+        message_queue.clear();
+        self.available.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn clone(&self) -> Self {
+        MessageQueue {
+            message_queue: self.message_queue.clone(),
+            available: self.available.clone(),
+            closed: self.closed.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread, time::Duration};
+
+    use super::*;
+
+    static WAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Parks a consumer on its own thread and reports what it got back.
+    fn parked_consumer(queue: &MessageQueue<u32>) -> mpsc::Receiver<Option<u32>> {
+        let (sender, receiver) = mpsc::channel();
+        let queue = queue.clone();
+
+        thread::spawn(move || sender.send(queue.consume_blocking()).unwrap());
+        thread::sleep(Duration::from_millis(50));
+
+        receiver
+    }
+
+    #[test]
+    fn parked_consumer_wakes_on_produce() {
+        let queue = MessageQueue::new();
+        let consumed = parked_consumer(&queue);
+
+        assert!(consumed.try_recv().is_err());
+        queue.produce(7);
+        assert_eq!(consumed.recv_timeout(WAKE_TIMEOUT), Ok(Some(7)));
+    }
+
+    #[test]
+    fn parked_consumer_wakes_on_close() {
+        let queue = MessageQueue::new();
+        let consumed = parked_consumer(&queue);
+
+        queue.close();
+        assert_eq!(consumed.recv_timeout(WAKE_TIMEOUT), Ok(None));
+
+        queue.produce(7);
+        assert_eq!(queue.consume_blocking(), None);
+    }
+}
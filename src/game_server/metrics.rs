@@ -0,0 +1,55 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds of the histogram buckets in microseconds. Anything slower
+/// lands in the implicit `+Inf` bucket.
+static BUCKETS_US: [u64; 9] = [50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000];
+
+/// Lock-free latency histogram, rendered in the Prometheus text format.
+pub struct LatencyHistogram {
+    counts: [AtomicU64; 10],
+    sum_us: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram {
+            counts: Default::default(),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        let bucket = BUCKETS_US.iter().position(|bound| micros <= *bound).unwrap_or(BUCKETS_US.len());
+
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Cumulative buckets as Prometheus expects them, in seconds.
+    pub fn render(&self, name: &str) -> String {
+        let mut output = format!("# TYPE {} histogram\n", name);
+        let mut cumulative = 0;
+
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+
+            let bound = match BUCKETS_US.get(i) {
+                Some(v) => (*v as f64 / 1_000_000.0).to_string(),
+                None => "+Inf".to_owned(),
+            };
+
+            output.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, cumulative));
+        }
+
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+
+        output.push_str(&format!("{}_sum {}\n", name, sum));
+        output.push_str(&format!("{}_count {}\n", name, cumulative));
+
+        output
+    }
+}
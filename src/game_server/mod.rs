@@ -1,4 +1,11 @@
-pub mod game_server;
-pub mod message_queue;
-pub mod chess_game;
-pub mod tandem_game;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod game_server;
+pub mod message_queue;
+pub mod chess_game;
+pub mod tandem_game;
+pub mod settings;
+pub mod activity_log;
+pub mod evaluation;
+pub mod metrics;
+pub mod slow_mode;
+pub mod protocol;
@@ -0,0 +1,401 @@
+use chess::Color;
+use serde::Deserialize;
+
+use crate::game_server::tandem_game::{TandemMove, parse_seat, square_name};
+
+/// Version of the JSON messages, sent by clients as `v`.
+pub static PROTOCOL_VERSION: u32 = 1;
+
+/// Side of a board as clients name it, `W`/`B` or `white`/`black`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    #[serde(alias = "white")]
+    W,
+    #[serde(alias = "black")]
+    B,
+}
+
+impl Side {
+    pub fn color(self) -> Color {
+        match self {
+            Side::W => Color::White,
+            Side::B => Color::Black,
+        }
+    }
+}
+
+/// Everything a client can send. JSON messages name the variant in `type`,
+/// e.g. `{"v":1,"type":"move","board":1,"color":"W","from":"e2","to":"e4","piece":"wP"}`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Command {
+    Reset,
+    Abort,
+    Setting { key: String, value: String },
+    Admin { secret: String },
+    SetFen { board: String, fen: String, banks: String },
+    PingApp { nonce: String },
+    SetResult { outcome: String, reason: String },
+    Setup,
+    Place { board: String, square: String, piece: String },
+    Lock,
+    Slow { moves_per_second: u32 },
+    Sit { board: u8, color: Side },
+    Stand,
+    Title { title: String },
+    LastMove { board: u8 },
+    San { board: u8 },
+    Resign { board: u8, color: Side },
+    OfferDraw { board: u8, color: Side },
+    AcceptDraw { board: u8, color: Side },
+    Berserk { board: u8, color: Side },
+    Clock { board: u8, color: Side },
+    Move {
+        board: u8,
+        color: Side,
+        from: String,
+        to: String,
+        piece: String,
+        #[serde(default)]
+        promotion: Option<String>,
+        #[serde(default)]
+        id: Option<String>,
+    },
+    Drop {
+        board: u8,
+        color: Side,
+        piece: String,
+        to: String,
+        #[serde(default)]
+        id: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    v: u32,
+    #[serde(flatten)]
+    command: Command,
+}
+
+/// Why a message couldn't be turned into a command.
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    /// JSON that doesn't match any command, with serde's explanation.
+    Malformed(String),
+    UnsupportedVersion(u32),
+    /// A string in neither format. Legacy clients never heard about these.
+    Unknown,
+}
+
+impl ProtocolError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProtocolError::Malformed(_) => "malformed_message",
+            ProtocolError::UnsupportedVersion(_) => "unsupported_version",
+            ProtocolError::Unknown => "unknown_command",
+        }
+    }
+}
+
+/// Parses a JSON message, falling back to the deprecated semicolon format
+/// for anything that isn't a JSON object. The flag is set for the latter.
+pub fn parse(msg: &str) -> Result<(Command, bool), ProtocolError> {
+    if !msg.trim_start().starts_with('{') {
+        return Command::from_legacy(msg).map(|command| (command, true)).ok_or(ProtocolError::Unknown);
+    }
+
+    let envelope = serde_json::from_str::<Envelope>(msg).map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+
+    if envelope.v != PROTOCOL_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(envelope.v));
+    }
+
+    Ok((envelope.command, false))
+}
+
+impl Command {
+    /// Parses the semicolon separated format, e.g. `Setting;auto_queen;on` or
+    /// `1;W;e2;e4;wP;`. Deprecated in favor of the JSON messages.
+    pub fn from_legacy(msg: &str) -> Option<Self> {
+        let splitted = msg.split(';').collect::<Vec<&str>>();
+
+        // Commands addressing a seat, e.g. `Resign;1;W`.
+        let seat = || match splitted.len() {
+            3 => parse_seat(splitted[1], splitted[2]).map(|(board, color)| (board, side_of(color))),
+            _ => None,
+        };
+
+        let command = match splitted[0] {
+            "Reset Game" if splitted.len() == 1 => Command::Reset,
+            "Abort Game" if splitted.len() == 1 => Command::Abort,
+            "setup" if splitted.len() == 1 => Command::Setup,
+            "lock" if splitted.len() == 1 => Command::Lock,
+            "stand" if splitted.len() == 1 => Command::Stand,
+            "Setting" if splitted.len() == 3 => Command::Setting {
+                key: splitted[1].to_owned(),
+                value: splitted[2].to_owned(),
+            },
+            "admin" => Command::Admin { secret: rest_after(msg, "admin;") },
+            "setfen" if splitted.len() == 4 => Command::SetFen {
+                board: splitted[1].to_owned(),
+                fen: splitted[2].to_owned(),
+                banks: splitted[3].to_owned(),
+            },
+            "ping_app" => Command::PingApp { nonce: rest_after(msg, "ping_app;") },
+            "set_result" if splitted.len() == 3 => Command::SetResult {
+                outcome: splitted[1].to_owned(),
+                reason: splitted[2].to_owned(),
+            },
+            "place" if splitted.len() == 4 => Command::Place {
+                board: splitted[1].to_owned(),
+                square: splitted[2].to_owned(),
+                piece: splitted[3].to_owned(),
+            },
+            "slow" if splitted.len() == 2 => Command::Slow { moves_per_second: splitted[1].parse().ok()? },
+            "title" => Command::Title { title: rest_after(msg, "title;") },
+            "lastmove" if splitted.len() == 2 => Command::LastMove { board: splitted[1].parse().ok()? },
+            "san" if splitted.len() == 2 => Command::San { board: splitted[1].parse().ok()? },
+            "sit" => seat().map(|(board, color)| Command::Sit { board, color })?,
+            "Resign" => seat().map(|(board, color)| Command::Resign { board, color })?,
+            "OfferDraw" => seat().map(|(board, color)| Command::OfferDraw { board, color })?,
+            "AcceptDraw" => seat().map(|(board, color)| Command::AcceptDraw { board, color })?,
+            "berserk" => seat().map(|(board, color)| Command::Berserk { board, color })?,
+            "clock" => seat().map(|(board, color)| Command::Clock { board, color })?,
+            _ => Command::from_move(TandemMove::from_string(msg.to_owned())?),
+        };
+
+        Some(command)
+    }
+
+    fn from_move(tandem_move: TandemMove) -> Self {
+        let color = side_of(tandem_move.color);
+
+        if tandem_move.source == "spare" {
+            return Command::Drop {
+                board: tandem_move.board,
+                color,
+                piece: tandem_move.piece,
+                to: tandem_move.target,
+                id: tandem_move.id,
+            };
+        }
+
+        Command::Move {
+            board: tandem_move.board,
+            color,
+            from: tandem_move.source,
+            to: tandem_move.target,
+            piece: tandem_move.piece,
+            promotion: Some(tandem_move.promotion).filter(|promotion| !promotion.is_empty()),
+            id: tandem_move.id,
+        }
+    }
+
+    /// The move or drop as the game takes it, `None` for other commands or a
+    /// board that doesn't exist. Squares may be indices, like in the legacy
+    /// format.
+    pub fn to_tandem_move(&self) -> Option<TandemMove> {
+        let tandem_move = match self {
+            Command::Move { board, color, from, to, piece, promotion, id } => TandemMove {
+                board: *board,
+                color: color.color(),
+                source: square_name(from),
+                target: square_name(to),
+                piece: piece.clone(),
+                promotion: promotion.as_deref().map(square_name).unwrap_or_default(),
+                id: id.clone().filter(|id| !id.is_empty()),
+            },
+            Command::Drop { board, color, piece, to, id } => TandemMove {
+                board: *board,
+                color: color.color(),
+                source: "spare".to_owned(),
+                target: square_name(to),
+                piece: piece.clone(),
+                promotion: String::new(),
+                id: id.clone().filter(|id| !id.is_empty()),
+            },
+            _ => return None,
+        };
+
+        (tandem_move.board == 1 || tandem_move.board == 2).then_some(tandem_move)
+    }
+}
+
+fn side_of(color: Color) -> Side {
+    match color {
+        Color::White => Side::W,
+        _ => Side::B,
+    }
+}
+
+/// Everything after the command name, semicolons included, as titles and
+/// nonces are free text.
+fn rest_after(msg: &str, prefix: &str) -> String {
+    msg.strip_prefix(prefix).unwrap_or_default().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(msg: &str) -> Command {
+        match parse(msg) {
+            Ok((command, legacy)) => {
+                assert!(!legacy);
+                command
+            },
+            Err(e) => panic!("{} didn't parse: {:?}", msg, e),
+        }
+    }
+
+    fn legacy(msg: &str) -> Command {
+        match parse(msg) {
+            Ok((command, legacy)) => {
+                assert!(legacy);
+                command
+            },
+            Err(e) => panic!("{} didn't parse: {:?}", msg, e),
+        }
+    }
+
+    #[test]
+    fn parses_every_json_command() {
+        assert_eq!(json(r#"{"v":1,"type":"reset"}"#), Command::Reset);
+        assert_eq!(json(r#"{"v":1,"type":"abort"}"#), Command::Abort);
+        assert_eq!(json(r#"{"v":1,"type":"setting","key":"auto_queen","value":"on"}"#),
+            Command::Setting { key: "auto_queen".to_owned(), value: "on".to_owned() });
+        assert_eq!(json(r#"{"v":1,"type":"admin","secret":"s"}"#), Command::Admin { secret: "s".to_owned() });
+        assert_eq!(json(r#"{"v":1,"type":"set_fen","board":"1","fen":"8/8/8/8/8/8/8/8 w - - 0 1","banks":"Qp"}"#),
+            Command::SetFen { board: "1".to_owned(), fen: "8/8/8/8/8/8/8/8 w - - 0 1".to_owned(), banks: "Qp".to_owned() });
+        assert_eq!(json(r#"{"v":1,"type":"ping_app","nonce":"42"}"#), Command::PingApp { nonce: "42".to_owned() });
+        assert_eq!(json(r#"{"v":1,"type":"set_result","outcome":"A","reason":"illegal"}"#),
+            Command::SetResult { outcome: "A".to_owned(), reason: "illegal".to_owned() });
+        assert_eq!(json(r#"{"v":1,"type":"setup"}"#), Command::Setup);
+        assert_eq!(json(r#"{"v":1,"type":"place","board":"2","square":"e4","piece":"wN"}"#),
+            Command::Place { board: "2".to_owned(), square: "e4".to_owned(), piece: "wN".to_owned() });
+        assert_eq!(json(r#"{"v":1,"type":"lock"}"#), Command::Lock);
+        assert_eq!(json(r#"{"v":1,"type":"slow","moves_per_second":5}"#), Command::Slow { moves_per_second: 5 });
+        assert_eq!(json(r#"{"v":1,"type":"stand"}"#), Command::Stand);
+        assert_eq!(json(r#"{"v":1,"type":"title","title":"Round 1"}"#), Command::Title { title: "Round 1".to_owned() });
+        assert_eq!(json(r#"{"v":1,"type":"last_move","board":2}"#), Command::LastMove { board: 2 });
+        assert_eq!(json(r#"{"v":1,"type":"san","board":1}"#), Command::San { board: 1 });
+
+        let (board, color) = (1, Side::W);
+        assert_eq!(json(r#"{"v":1,"type":"sit","board":1,"color":"W"}"#), Command::Sit { board, color });
+        assert_eq!(json(r#"{"v":1,"type":"resign","board":1,"color":"white"}"#), Command::Resign { board, color });
+
+        let (board, color) = (2, Side::B);
+        assert_eq!(json(r#"{"v":1,"type":"offer_draw","board":2,"color":"B"}"#), Command::OfferDraw { board, color });
+        assert_eq!(json(r#"{"v":1,"type":"accept_draw","board":2,"color":"black"}"#), Command::AcceptDraw { board, color });
+        assert_eq!(json(r#"{"v":1,"type":"berserk","board":2,"color":"B"}"#), Command::Berserk { board, color });
+        assert_eq!(json(r#"{"v":1,"type":"clock","board":2,"color":"B"}"#), Command::Clock { board, color });
+
+        assert_eq!(json(r#"{"v":1,"type":"move","board":1,"color":"W","from":"e7","to":"e8","piece":"wP","promotion":"d1","id":"m1"}"#),
+            Command::Move {
+                board: 1,
+                color: Side::W,
+                from: "e7".to_owned(),
+                to: "e8".to_owned(),
+                piece: "wP".to_owned(),
+                promotion: Some("d1".to_owned()),
+                id: Some("m1".to_owned()),
+            });
+        assert_eq!(json(r#"{"v":1,"type":"drop","board":2,"color":"B","piece":"bN","to":"f6"}"#),
+            Command::Drop { board: 2, color: Side::B, piece: "bN".to_owned(), to: "f6".to_owned(), id: None });
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert_eq!(parse(r#"{"v":2,"type":"reset"}"#), Err(ProtocolError::UnsupportedVersion(2)));
+        assert_eq!(parse(r#"{"v":0,"type":"reset"}"#), Err(ProtocolError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        let malformed = [
+            r#"{"type":"reset"}"#,
+            r#"{"v":1}"#,
+            r#"{"v":1,"type":"castle"}"#,
+            r#"{"v":1,"type":"sit","board":1}"#,
+            r#"{"v":1,"type":"sit","board":1,"color":"red"}"#,
+            r#"{"v":1,"type":"sit","board":"one","color":"W"}"#,
+            r#"{"v":1,"type":"slow","moves_per_second":-1}"#,
+            r#"{"v":1,"type":"ping_app","nonce":"1","extra":true}"#,
+            r#"{"v":1,"type":"reset""#,
+        ];
+
+        for msg in malformed {
+            match parse(msg) {
+                Err(e @ ProtocolError::Malformed(_)) => assert_eq!(e.code(), "malformed_message"),
+                other => panic!("{} gave {:?}", msg, other),
+            }
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_legacy_format() {
+        assert_eq!(legacy("Reset Game"), Command::Reset);
+        assert_eq!(legacy("Abort Game"), Command::Abort);
+        assert_eq!(legacy("Setting;auto_queen;on"), Command::Setting { key: "auto_queen".to_owned(), value: "on".to_owned() });
+        assert_eq!(legacy("admin;a;b"), Command::Admin { secret: "a;b".to_owned() });
+        assert_eq!(legacy("ping_app;7"), Command::PingApp { nonce: "7".to_owned() });
+        assert_eq!(legacy("slow;3"), Command::Slow { moves_per_second: 3 });
+        assert_eq!(legacy("title;Final; game 2"), Command::Title { title: "Final; game 2".to_owned() });
+        assert_eq!(legacy("lastmove;1"), Command::LastMove { board: 1 });
+        assert_eq!(legacy("sit;2;B"), Command::Sit { board: 2, color: Side::B });
+        assert_eq!(legacy("Resign;1;W"), Command::Resign { board: 1, color: Side::W });
+        assert_eq!(legacy("stand"), Command::Stand);
+        assert_eq!(legacy("1;W;e2;e4;wP;"), Command::Move {
+            board: 1,
+            color: Side::W,
+            from: "e2".to_owned(),
+            to: "e4".to_owned(),
+            piece: "wP".to_owned(),
+            promotion: None,
+            id: None,
+        });
+        assert_eq!(legacy("2;B;spare;f6;bN;;d7"), Command::Drop {
+            board: 2,
+            color: Side::B,
+            piece: "bN".to_owned(),
+            to: "f6".to_owned(),
+            id: Some("d7".to_owned()),
+        });
+    }
+
+    #[test]
+    fn ignores_unknown_legacy_strings() {
+        for msg in ["", "hello", "Reset Game;now", "sit;3;W", "slow;fast", "1;W;e2"] {
+            assert_eq!(parse(msg), Err(ProtocolError::Unknown), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn json_moves_accept_square_indices_like_the_legacy_format() {
+        let command = json(r#"{"v":1,"type":"move","board":1,"color":"W","from":"12","to":"28","piece":"wP"}"#);
+        let legacy_move = TandemMove::from_string("1;W;12;28;wP;".to_owned()).unwrap();
+        let tandem_move = command.to_tandem_move().unwrap();
+
+        assert_eq!((tandem_move.source.as_str(), tandem_move.target.as_str()), ("e2", "e4"));
+        assert_eq!((tandem_move.source, tandem_move.target), (legacy_move.source, legacy_move.target));
+
+        let command = json(r#"{"v":1,"type":"drop","board":1,"color":"W","piece":"wN","to":"36"}"#);
+        assert_eq!(command.to_tandem_move().unwrap().target, "e5");
+
+        let command = json(r#"{"v":1,"type":"move","board":3,"color":"W","from":"e2","to":"e4","piece":"wP"}"#);
+        assert!(command.to_tandem_move().is_none());
+    }
+
+    #[test]
+    fn empty_move_ids_count_as_missing() {
+        let command = json(r#"{"v":1,"type":"move","board":1,"color":"W","from":"e2","to":"e4","piece":"wP","id":""}"#);
+        assert_eq!(command.to_tandem_move().unwrap().id, None);
+
+        let command = json(r#"{"v":1,"type":"drop","board":1,"color":"W","piece":"wN","to":"e5","id":""}"#);
+        assert_eq!(command.to_tandem_move().unwrap().id, None);
+
+        assert_eq!(TandemMove::from_string("1;W;e2;e4;wP;;".to_owned()).unwrap().id, None);
+        assert_eq!(TandemMove::from_string("1;W;e2;e4;wP;;m1".to_owned()).unwrap().id.as_deref(), Some("m1"));
+    }
+}
@@ -0,0 +1,411 @@
+use chess::Color;
+use serde_json::{json, Value};
+
+use crate::game_server::evaluation::DEFAULT_PIECE_VALUES;
+use crate::game_server::tandem_game::Team;
+
+/// Most pieces of one type and color that exist across both boards, in bank
+/// order (queen, rook, bishop, knight, pawn). Material only moves between the
+/// boards, so a bank can't legitimately hold more.
+static DEFAULT_BANK_CAP: [i32; 5] = [2, 4, 4, 4, 16];
+
+/// Longest base time a time control may ask for.
+static MAX_BASE_MINUTES: i64 = 180;
+static MAX_INCREMENT_SECONDS: i64 = 60;
+
+/// Highest centipawn value a piece may be given, low enough that a whole
+/// board plus full banks still fits the evaluation's i32.
+static MAX_PIECE_VALUE: i32 = 10_000;
+
+/// Starting time and Fischer increment of both boards, written like `3+2`
+/// for three minutes plus two seconds a move.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeControl {
+    pub base_ms: i64,
+    pub increment_ms: i64,
+}
+
+impl TimeControl {
+    /// Five minutes without increment, what every game used before time
+    /// controls could be chosen.
+    pub fn default_control() -> Self {
+        TimeControl { base_ms: 5 * 60 * 1000, increment_ms: 0 }
+    }
+
+    /// Parses `<minutes>+<seconds>`, e.g. `3+2`, `5+0` or `1+0`. The base has
+    /// to be at least a minute.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (minutes, seconds) = value.split_once('+')?;
+        let minutes = minutes.parse::<i64>().ok().filter(|v| (1..=MAX_BASE_MINUTES).contains(v))?;
+        let seconds = seconds.parse::<i64>().ok().filter(|v| (0..=MAX_INCREMENT_SECONDS).contains(v))?;
+
+        Some(TimeControl { base_ms: minutes * 60 * 1000, increment_ms: seconds * 1000 })
+    }
+
+    pub fn name(self) -> String {
+        format!("{}+{}", self.base_ms / 60_000, self.increment_ms / 1000)
+    }
+}
+
+/// Increment that starts at `start_ms` and shrinks linearly to zero over a
+/// side's first `moves` moves. Disabled while either value is zero.
+#[derive(Clone, Copy)]
+pub struct DecayingIncrement {
+    pub start_ms: i64,
+    pub moves: u32,
+}
+
+impl DecayingIncrement {
+    /// Time credited for a side's `move_number`-th move, counting from one.
+    pub fn increment_for(&self, move_number: u32) -> i64 {
+        if move_number == 0 || move_number > self.moves {
+            return 0;
+        }
+
+        let remaining = (self.moves - move_number + 1) as i128;
+
+        (self.start_ms as i128 * remaining / self.moves as i128) as i64
+    }
+}
+
+/// When the match ends. Under `FirstBoardDecides` the first decisive board
+/// ends everything. Under `BothBoardsConsidered` a checkmate only ends its
+/// own board and the match is scored once both boards are mated; a flag still
+/// ends the match right away.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TerminationPolicy {
+    FirstBoardDecides,
+    BothBoardsConsidered,
+}
+
+impl TerminationPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            TerminationPolicy::FirstBoardDecides => "first_board",
+            TerminationPolicy::BothBoardsConsidered => "both_boards",
+        }
+    }
+}
+
+/// What happens when the side to move has no legal move or drop but isn't in
+/// check. `Draw` is the standard rule. Under `LossOnMove` the stuck side
+/// would only flag while the clock runs, so that is scored as a loss right
+/// away. Both end the whole match rather than just that board, whatever the
+/// `TerminationPolicy`, since the other board can't be scored on its own.
+#[derive(Clone, Copy, PartialEq)]
+pub enum StalematePolicy {
+    Draw,
+    LossOnMove,
+}
+
+impl StalematePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            StalematePolicy::Draw => "draw",
+            StalematePolicy::LossOnMove => "loss",
+        }
+    }
+}
+
+/// What a flag means. `AlwaysLoss` loses on time no matter what, while
+/// `DrawIfUnmatable` draws when the opponent has neither mating material on
+/// the board nor anything in the bank to drop.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlagPolicy {
+    AlwaysLoss,
+    DrawIfUnmatable,
+}
+
+impl FlagPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            FlagPolicy::AlwaysLoss => "loss",
+            FlagPolicy::DrawIfUnmatable => "draw_if_unmatable",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GameSettings {
+    pub auto_queen: bool,
+    pub check_bonus_ms: i64,
+    /// Draws the match once no capture or drop can bring mating material
+    /// back. The check only fires when that's certain, so it's on by default.
+    pub dead_position_draw: bool,
+    /// Most pieces of each type a bank holds, see `bank_cap()` for the cap
+    /// actually applied.
+    pub bank_cap: [i32; 5],
+    /// Which color clients should draw at the bottom of each board. Partners
+    /// face each other, so by default board 1 is white-bottom and board 2
+    /// black-bottom.
+    pub orientation: [Color; 2],
+    /// Starting time and Fischer increment per move on both boards.
+    pub time_control: TimeControl,
+    pub decaying_increment: DecayingIncrement,
+    /// Credit each move's increment to the partner's clock instead.
+    pub increment_to_partner: bool,
+    /// Armageddon: the team that wins the match if it ends in a draw.
+    pub draw_odds: Option<Team>,
+    /// Warn when a move leaves the partner facing a mate in one.
+    pub mate_warning: bool,
+    /// Each side's first this many moves don't run its clock.
+    pub free_moves: u32,
+    /// Centipawn value of each piece type in bank order, used wherever
+    /// material is summed.
+    pub piece_values: [i32; 5],
+    pub termination: TerminationPolicy,
+    pub stalemate: StalematePolicy,
+    pub flag: FlagPolicy,
+}
+
+impl GameSettings {
+    pub fn new() -> Self {
+        GameSettings {
+            auto_queen: false,
+            check_bonus_ms: 0,
+            dead_position_draw: true,
+            bank_cap: DEFAULT_BANK_CAP,
+            orientation: [Color::White, Color::Black],
+            time_control: TimeControl::default_control(),
+            decaying_increment: DecayingIncrement { start_ms: 0, moves: 0 },
+            increment_to_partner: false,
+            draw_odds: None,
+            mate_warning: false,
+            free_moves: 0,
+            piece_values: DEFAULT_PIECE_VALUES,
+            termination: TerminationPolicy::FirstBoardDecides,
+            stalemate: StalematePolicy::Draw,
+            flag: FlagPolicy::AlwaysLoss,
+        }
+    }
+
+    /// Changes a single setting by name. Returns false if the key is unknown
+    /// or the value can't be parsed, leaving the settings untouched.
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "auto_queen" => match parse_bool(value) {
+                Some(v) => self.auto_queen = v,
+                None => return false,
+            },
+            // A bonus can't add more than a full clock anyway.
+            "check_bonus_ms" => match value.parse::<i64>() {
+                Ok(v) if (0..=self.time_control.base_ms).contains(&v) => self.check_bonus_ms = v,
+                _ => return false,
+            },
+            "dead_position_draw" => match parse_bool(value) {
+                Some(v) => self.dead_position_draw = v,
+                None => return false,
+            },
+            "bank_cap" => match parse_bank(value) {
+                Some(v) => self.bank_cap = v,
+                None => return false,
+            },
+            "orientation" => match parse_orientation(value) {
+                Some(v) => self.orientation = v,
+                None => return false,
+            },
+            "increment_ms" => match value.parse::<i64>() {
+                Ok(v) if (0..=MAX_INCREMENT_SECONDS * 1000).contains(&v) => self.time_control.increment_ms = v,
+                _ => return false,
+            },
+            "time_control" => match TimeControl::parse(value) {
+                Some(v) => self.time_control = v,
+                None => return false,
+            },
+            "decaying_increment" => match parse_decaying_increment(value) {
+                Some(v) => self.decaying_increment = v,
+                None => return false,
+            },
+            "flag" => match value {
+                "loss" => self.flag = FlagPolicy::AlwaysLoss,
+                "draw_if_unmatable" => self.flag = FlagPolicy::DrawIfUnmatable,
+                _ => return false,
+            },
+            "stalemate" => match value {
+                "draw" => self.stalemate = StalematePolicy::Draw,
+                "loss" => self.stalemate = StalematePolicy::LossOnMove,
+                _ => return false,
+            },
+            "termination" => match value {
+                "first_board" => self.termination = TerminationPolicy::FirstBoardDecides,
+                "both_boards" => self.termination = TerminationPolicy::BothBoardsConsidered,
+                _ => return false,
+            },
+            "piece_values" => match parse_bank(value) {
+                Some(v) if v.iter().all(|v| *v <= MAX_PIECE_VALUE) => self.piece_values = v,
+                _ => return false,
+            },
+            "free_moves" => match value.parse::<u32>() {
+                Ok(v) => self.free_moves = v,
+                Err(_) => return false,
+            },
+            "increment_to_partner" => match parse_bool(value) {
+                Some(v) => self.increment_to_partner = v,
+                None => return false,
+            },
+            "mate_warning" => match parse_bool(value) {
+                Some(v) => self.mate_warning = v,
+                None => return false,
+            },
+            "draw_odds" => match value {
+                "A" => self.draw_odds = Some(Team::A),
+                "B" => self.draw_odds = Some(Team::B),
+                "off" => self.draw_odds = None,
+                _ => return false,
+            },
+            _ => return false,
+        };
+
+        true
+    }
+
+    /// Cap applied to the banks. Auto-queen creates a queen for every pawn
+    /// that promotes, so while it's on a bank may also hold one queen per
+    /// pawn the cap allows.
+    pub fn bank_cap(&self) -> [i32; 5] {
+        let mut bank_cap = self.bank_cap;
+
+        if self.auto_queen {
+            bank_cap[0] = bank_cap[0].saturating_add(bank_cap[4]);
+        }
+
+        bank_cap
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "auto_queen": self.auto_queen,
+            "check_bonus_ms": self.check_bonus_ms,
+            "dead_position_draw": self.dead_position_draw,
+            "bank_cap": self.bank_cap,
+            "orientation": {
+                "board_1": color_name(self.orientation[0]),
+                "board_2": color_name(self.orientation[1]),
+            },
+            "time_control": self.time_control.name(),
+            "base_ms": self.time_control.base_ms,
+            "increment_ms": self.time_control.increment_ms,
+            "decaying_increment": {
+                "start_ms": self.decaying_increment.start_ms,
+                "moves": self.decaying_increment.moves,
+            },
+            "increment_to_partner": self.increment_to_partner,
+            "draw_odds": self.draw_odds.map(Team::name),
+            "mate_warning": self.mate_warning,
+            "free_moves": self.free_moves,
+            "piece_values": self.piece_values,
+            "termination": self.termination.name(),
+            "stalemate": self.stalemate.name(),
+            "flag": self.flag.name(),
+        })
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses five comma separated counts in bank order, e.g. `2,4,4,4,16`.
+fn parse_bank(value: &str) -> Option<[i32; 5]> {
+    let counts = value.split(',')
+        .map(|count| count.parse::<i32>().ok().filter(|v| *v >= 0))
+        .collect::<Option<Vec<i32>>>()?;
+
+    counts.try_into().ok()
+}
+
+/// Parses the bottom color of both boards, e.g. `white,black`.
+fn parse_orientation(value: &str) -> Option<[Color; 2]> {
+    let colors = value.split(',')
+        .map(|color| match color {
+            "white" => Some(Color::White),
+            "black" => Some(Color::Black),
+            _ => None,
+        })
+        .collect::<Option<Vec<Color>>>()?;
+
+    colors.try_into().ok()
+}
+
+/// Parses the starting increment and the number of moves it decays over,
+/// e.g. `5000,20`. The start is limited like a time control's increment.
+fn parse_decaying_increment(value: &str) -> Option<DecayingIncrement> {
+    let (start_ms, moves) = value.split_once(',')?;
+    let start_ms = start_ms.parse::<i64>().ok().filter(|v| (0..=MAX_INCREMENT_SECONDS * 1000).contains(v))?;
+    let moves = moves.parse::<u32>().ok()?;
+
+    Some(DecayingIncrement { start_ms, moves })
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "white",
+        _ => "black",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increment_is_limited_like_time_controls() {
+        let mut settings = GameSettings::new();
+
+        assert!(settings.set("increment_ms", "60000"));
+        assert!(!settings.set("increment_ms", "60001"));
+        assert!(!settings.set("increment_ms", "9223372036854775807"));
+        assert_eq!(settings.time_control.increment_ms, 60_000);
+    }
+
+    #[test]
+    fn check_bonus_is_limited_to_the_base_time() {
+        let mut settings = GameSettings::new();
+
+        assert!(settings.set("check_bonus_ms", "300000"));
+        assert!(!settings.set("check_bonus_ms", "300001"));
+        assert!(!settings.set("check_bonus_ms", "9223372036854775807"));
+        assert_eq!(settings.check_bonus_ms, 300_000);
+    }
+
+    #[test]
+    fn decaying_increment_is_limited_and_does_not_overflow() {
+        let mut settings = GameSettings::new();
+
+        assert!(settings.set("decaying_increment", "60000,5"));
+        assert!(!settings.set("decaying_increment", "60001,5"));
+        assert!(!settings.set("decaying_increment", "9223372036854775807,5"));
+
+        let increment = DecayingIncrement { start_ms: i64::MAX, moves: u32::MAX };
+        assert_eq!(increment.increment_for(1), i64::MAX);
+        assert_eq!(settings.decaying_increment.increment_for(1), 60_000);
+        assert_eq!(settings.decaying_increment.increment_for(5), 12_000);
+        assert_eq!(settings.decaying_increment.increment_for(6), 0);
+    }
+
+    #[test]
+    fn piece_values_are_limited() {
+        let mut settings = GameSettings::new();
+
+        assert!(settings.set("piece_values", "10000,500,330,320,100"));
+        assert!(!settings.set("piece_values", "1,1,1,1,2147483647"));
+        assert!(!settings.set("piece_values", "900,500,330,320,-100"));
+        assert_eq!(settings.piece_values, [10_000, 500, 330, 320, 100]);
+    }
+
+    #[test]
+    fn auto_queen_raises_the_queen_cap() {
+        let mut settings = GameSettings::new();
+        assert_eq!(settings.bank_cap(), [2, 4, 4, 4, 16]);
+
+        assert!(settings.set("auto_queen", "on"));
+        assert_eq!(settings.bank_cap(), [18, 4, 4, 4, 16]);
+
+        assert!(settings.set("bank_cap", "1,2,2,2,3"));
+        assert_eq!(settings.bank_cap(), [4, 2, 2, 2, 3]);
+    }
+}
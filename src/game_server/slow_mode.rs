@@ -0,0 +1,68 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+static WINDOW: Duration = Duration::from_secs(1);
+
+struct Window {
+    /// Moves allowed per second across the whole server, `None` when off.
+    cap: Option<u32>,
+    start: Instant,
+    count: u32,
+}
+
+/// Server wide move throttle an admin can switch on to protect a small
+/// instance. Moves beyond the cap within a one second window are refused.
+pub struct SlowMode {
+    window: Mutex<Window>,
+}
+
+impl SlowMode {
+    pub fn new() -> Self {
+        SlowMode {
+            window: Mutex::new(Window {
+                cap: None,
+                start: Instant::now(),
+                count: 0,
+            }),
+        }
+    }
+
+    /// Sets the cap in moves per second, `0` switches slow mode off.
+    pub fn set_cap(&self, moves_per_second: u32) {
+        let mut window = self.window.lock().unwrap();
+
+        window.cap = (moves_per_second > 0).then_some(moves_per_second);
+        window.start = Instant::now();
+        window.count = 0;
+    }
+
+    pub fn cap(&self) -> Option<u32> {
+        self.window.lock().unwrap().cap
+    }
+
+    /// Takes one move from the current window. Returns false if the cap is
+    /// already used up.
+    pub fn admit(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+
+        let cap = match window.cap {
+            Some(v) => v,
+            None => return true,
+        };
+
+        if window.start.elapsed() >= WINDOW {
+            window.start = Instant::now();
+            window.count = 0;
+        }
+
+        if window.count >= cap {
+            return false;
+        }
+
+        window.count += 1;
+
+        true
+    }
+}
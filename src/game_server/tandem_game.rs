@@ -1,360 +1,2476 @@
-use std::{
-    sync::{Arc, RwLock},
-    ops::BitAnd,
-};
-use chess::{Board, Square, ChessMove, Piece, Color, Rank, BoardStatus, BoardBuilder, BitBoard};
-
-use serde_json::json;
-use chrono::Utc;
-
-use crate::game_server::chess_game::ChessGame;
-
-#[derive(Debug)]
-pub struct TandemMove {
-    pub board: u8,
-    pub color: Color,
-    pub source: String,
-    pub target: String,
-    pub piece: String,
-    pub promotion: String,
-}
-
-impl TandemMove {
-    pub fn from_string(tandem_string: String) -> Option<Self> {
-        let splitted = tandem_string.split(';').collect::<Vec<&str>>();
-
-        if splitted.len() != 6 {
-            return None;
-        }
-
-        let color = match splitted[1] {
-            "W" => Color::White,
-            _ => Color::Black,
-        };
-
-        let board = splitted[0].parse::<u8>().unwrap_or(0);
-
-        if board != 1 && board != 2 {
-            return None;
-        }
-
-        Some(TandemMove {
-            board: board,
-            color: color,
-            source: splitted[2].to_owned(),
-            target: splitted[3].to_owned(),
-            piece: splitted[4].to_owned(),
-            promotion: splitted[5].to_owned(),
-        })
-    }
-}
-
-pub struct TandemGame {
-    pub games: [ChessGame; 2],
-    started: bool,
-    finished: bool,
-    last_sync: i64,
-}
-
-impl TandemGame {
-    pub fn new() -> Self {
-        TandemGame {
-            games: [ChessGame::new(), ChessGame::new()],
-            finished: false,
-            started: false,
-            last_sync: 0,
-        }
-    }
-
-    pub fn get_fen(&self, valid: bool) -> String {
-        json!({
-            "valid": valid,
-            "board_1": self.games[0].to_string(),
-            "board_2": self.games[1].to_string(),
-        }).to_string()
-    }
-
-    pub fn should_update(&mut self) -> bool {
-        if self.finished {
-            return false;
-        }
-
-        self.synchronize_time();
-
-        self.games[0].should_update() 
-        || self.games[1].should_update()
-    }
-
-    pub fn reset(&mut self) {
-        for i in 0..2 {
-            self.games[i] = ChessGame::new();
-        }
-
-        self.started = false;
-        self.finished = false;
-        self.last_sync = 0;
-    }
-
-    pub fn synchronize_time(&mut self) {
-        if !self.started {
-            return;
-        }
-
-        let now = Utc::now().timestamp_millis();
-
-        if self.last_sync == 0 {
-            self.last_sync = now;
-        }
-
-        let time_dif = (now - self.last_sync).max(0);
-        self.last_sync = now;
-
-        for i in 0..2 {
-            self.games[i].synchronize_time(time_dif);
-
-            self.finished |= self.games[i].flagged();
-        }
-    }
-
-    pub fn move_piece(&mut self, tandem_move: &TandemMove) -> bool {
-        println!("{:?}", tandem_move);
-        self.synchronize_time();
-
-        if self.finished {
-            return false;
-        }
-
-        if tandem_move.board <= 0 {
-            return false;
-        }
-
-        let b_ind = (tandem_move.board - 1) as usize;
-        let o_ind = (b_ind + 1) % 2;
-
-        let board = self.games[b_ind].board;
-        let other_board = self.games[o_ind].board;
-
-        if board.side_to_move() != tandem_move.color {
-            return false;
-        }
-
-        let target = match Square::from_string(tandem_move.target.clone()) {
-            Some(v) => v,
-            None => return false,
-        };
-
-        match board.piece_on(target) {
-            Some(v) => { 
-                if v == Piece::King {
-                    println!("Tried to capture King");
-                    return false;
-                }
-            },
-            None => (),
-        };
-
-        if tandem_move.source == "spare" {
-            let _ = match board.piece_on(target) {
-                Some(_) => return false,
-                None => (),
-            };
-
-            let chars = tandem_move.piece.as_bytes();
-
-            if chars.len() != 2 {
-                return false;
-            }
-
-            let color = match chars[0] as char {
-                'w' => Color::White,
-                'b' => Color::Black,
-                _ => return false,
-            };
-
-            let piece = match chars[1] as char {
-                'P' => Piece::Pawn,
-                'N' => Piece::Knight,
-                'B' => Piece::Bishop,
-                'R' => Piece::Rook,
-                'Q' => Piece::Queen,
-                _ => return false,
-            };
-
-            if piece == Piece::Pawn {
-                if target.get_rank() == Rank::First 
-                || target.get_rank() == Rank::Eighth {
-                    return false;
-                }
-            }
-
-            let board_new = match set_piece_on_board(&board, piece, color, target) {
-                Some(v) => v,
-                None => return false,
-            };
-
-            if !self.games[b_ind].decrease_count(&color, piece) {
-                return false;
-            }
-
-            self.games[b_ind].board = board_new;
-            self.games[b_ind].change_turn(tandem_move.source.clone() + "-" + &tandem_move.target);
-
-            self.started = true;
-            return true;
-        }
-
-        let source = match Square::from_string(tandem_move.source.clone()) {
-            Some(v) => v,
-            None => return false,
-        };
-        let piece_source = match board.piece_on(source) {
-            Some(v) => v,
-            None => return false,
-        };
-        let rank = target.get_rank() as u8;
-        let is_promotion = piece_source == Piece::Pawn && (rank == 0 || rank == 7);
-
-        let promotion_target_op = Square::from_string(tandem_move.promotion.clone());
-        let mut promotion_piece_op = None;
-
-        if is_promotion {
-            match promotion_target_op {
-                Some(v) =>  {
-                    promotion_piece_op = other_board.piece_on(v);
-
-                    match other_board.color_on(v) {
-                        Some(v) => {
-                            if v != tandem_move.color {
-                                return false;
-                            }
-                        },
-                        None => return false,
-                    };
-                },
-                None => (),
-            };
-        }
-
-        let chess_move = ChessMove::new(source, target, promotion_piece_op);
-
-        if !board.legal(chess_move) {
-            return false;
-        }
-
-        if is_promotion {
-            let promotion_target = match promotion_target_op {
-                Some(v) => v,
-                None => return false,
-            };
-
-            println!("Checking Promotion valid");
-
-            let bit_board = other_board.pinned();
-            let square_board = BitBoard::from_square(promotion_target);
-
-            if bit_board.bitand(square_board).popcnt() > 0 {
-                return false;
-            }
-
-            let board_other = match other_board.clear_square(promotion_target) {
-                Some(v) => v,
-                None => return false,
-            };
-
-            self.games[o_ind].board = board_other;
-            self.games[o_ind].add_pawn(&tandem_move.color);
-        }
-
-        match board.piece_on(target) {
-            Some(v) => {
-                self.games[o_ind].add_piece(&tandem_move.color, v);
-                self.games[b_ind].last_move_capture(true);
-            },
-            None => self.games[b_ind].last_move_capture(false),
-        };
-
-        println!("{:?} {:?}", source, target);
-        self.games[b_ind].change_turn(tandem_move.source.clone() + "-" + &tandem_move.target);
-        self.games[b_ind].board = board.make_move_new(chess_move);
-
-        if is_mate(&self.games[b_ind].board, piece_source, target, tandem_move.color) {
-            self.finished = true;
-        }
-
-        self.started = true;
-        true
-    }
-}
-
-fn set_piece_on_board(board: &Board, piece: Piece, color: Color, target: Square) -> Option<Board> {
-    let target_x = target.get_rank() as i32;
-
-    if (target_x == 7 || target_x == 0) && piece == Piece::Pawn {
-        return None;
-    }
-
-    let mut board_builder = BoardBuilder::from(board);
-    board_builder.piece(target, piece, color);
-
-    match color {
-        Color::White => board_builder.side_to_move(Color::Black),
-        _ => board_builder.side_to_move(Color::White),
-    };
-
-    let new_board = match Board::try_from(board_builder) {
-        Ok(v) => v,
-        Err(_) => return None,
-    };
-
-    if is_mate(&new_board, piece, target, color) {
-        None
-    } else {
-        Some(new_board)
-    }
-}
-
-fn is_mate(board: &Board, piece: Piece, target: Square, color: Color) -> bool {
-    let target_x = target.get_rank() as i32;
-    let target_y = target.get_file() as i32;
-
-    let square_king = match color {
-        Color::White => board.king_square(Color::Black),
-        _ => board.king_square(Color::White),
-    };
-
-    let king_x = square_king.get_rank() as i32;
-    let king_y = square_king.get_file() as i32;
-
-    let close_chess = (king_x - target_x).abs().max((king_y - target_y).abs()) <= 1;
-
-    board.status() == BoardStatus::Checkmate && (close_chess || piece == Piece::Knight)
-}
-
-#[derive(Clone)]
-pub struct TandemGameInterface {
-    board: Arc<RwLock<TandemGame>>,
-}
-
-impl TandemGameInterface {
-    pub fn new() -> Self {
-        TandemGameInterface {
-            board: Arc::new(RwLock::new(TandemGame::new())),
-        }
-    }
-
-    pub fn get_fen(&self, valid: bool) -> String {
-        self.board.read().unwrap().get_fen(valid)
-    }
-
-    pub fn should_update(&self) -> bool {
-        self.board.write().unwrap().should_update()
-    }
-
-    pub fn reset(&self) {
-        self.board.write().unwrap().reset();
-    }
-
-    pub fn move_piece(&self, tandem_move: &TandemMove) -> bool {
-        self.board.write().unwrap().move_piece(tandem_move)
-    }
-}
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, RwLock},
+    ops::BitAnd,
+    str::FromStr,
+    time::Instant,
+};
+use chess::{Board, Square, ChessMove, Piece, Color, Rank, BoardStatus, BoardBuilder, BitBoard, MoveGen, ALL_SQUARES, get_rank, CastleRights, File};
+
+use serde_json::{json, Value};
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+
+use crate::game_server::activity_log::ActivityLog;
+use crate::game_server::chess_game::{ChessGame, LastMove, parse_bank_string};
+use crate::game_server::evaluation::{evaluate, evaluate_material};
+use crate::game_server::metrics::LatencyHistogram;
+use crate::game_server::slow_mode::SlowMode;
+use crate::game_server::settings::{FlagPolicy, GameSettings, StalematePolicy, TerminationPolicy, TimeControl};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TandemMove {
+    pub board: u8,
+    pub color: Color,
+    pub source: String,
+    pub target: String,
+    pub piece: String,
+    pub promotion: String,
+    /// Optional client correlation id, sent as a trailing seventh field.
+    pub id: Option<String>,
+}
+
+impl TandemMove {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "board": self.board,
+            "color": if self.color == Color::White { "W" } else { "B" },
+            "source": self.source,
+            "target": self.target,
+            "piece": self.piece,
+            "promotion": self.promotion,
+            "id": self.id,
+        })
+    }
+
+    pub fn from_string(tandem_string: String) -> Option<Self> {
+        let splitted = tandem_string.split(';').collect::<Vec<&str>>();
+
+        if splitted.len() != 6 && splitted.len() != 7 {
+            return None;
+        }
+
+        let color = match splitted[1] {
+            "W" => Color::White,
+            _ => Color::Black,
+        };
+
+        let board = splitted[0].parse::<u8>().unwrap_or(0);
+
+        if board != 1 && board != 2 {
+            return None;
+        }
+
+        Some(TandemMove {
+            board,
+            color,
+            source: square_name(splitted[2]),
+            target: square_name(splitted[3]),
+            piece: splitted[4].to_owned(),
+            promotion: square_name(splitted[5]),
+            id: splitted.get(6).filter(|id| !id.is_empty()).map(|id| id.to_string()),
+        })
+    }
+}
+
+/// Squares may also be sent as indices from 0 (a1) to 63 (h8). Those are
+/// turned into algebraic names, everything else is kept as is.
+pub fn square_name(square: &str) -> String {
+    match square.parse::<u8>() {
+        Ok(v) if v < 64 => ALL_SQUARES[v as usize].to_string(),
+        _ => square.to_owned(),
+    }
+}
+
+/// Serializes back into the wire format `from_string` accepts.
+impl fmt::Display for TandemMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let color = if self.color == Color::White { "W" } else { "B" };
+
+        write!(f, "{};{};{};{};{};{}", self.board, color, self.source, self.target, self.piece, self.promotion)?;
+
+        if let Some(id) = &self.id {
+            write!(f, ";{}", id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the `<board>;<color>` pair used by commands addressing a single
+/// seat, e.g. `1` and `W`.
+pub fn parse_seat(board: &str, color: &str) -> Option<(u8, Color)> {
+    let board = match board {
+        "1" => 1,
+        "2" => 2,
+        _ => return None,
+    };
+
+    let color = match color {
+        "W" => Color::White,
+        "B" => Color::Black,
+        _ => return None,
+    };
+
+    Some((board, color))
+}
+
+static RECENT_MOVE_IDS: usize = 32;
+/// Material difference in centipawns above which an imported position is
+/// flagged as imbalanced.
+static IMBALANCED_MATERIAL: i32 = 1_500;
+static MAX_TITLE_LENGTH: usize = 120;
+
+/// Index of a seat into per-seat arrays, board 1 white first.
+fn seat_index(board: u8, color: Color) -> usize {
+    (board as usize - 1) * 2 + color.to_index()
+}
+
+/// Team A plays white on board 1 and black on board 2, team B the other two
+/// seats.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Team {
+    A,
+    B,
+}
+
+impl Team {
+    pub fn of(board: u8, color: Color) -> Team {
+        match (board, color) {
+            (1, Color::White) | (2, Color::Black) => Team::A,
+            _ => Team::B,
+        }
+    }
+
+    pub fn opponent(self) -> Team {
+        match self {
+            Team::A => Team::B,
+            Team::B => Team::A,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Team::A => "A",
+            Team::B => "B",
+        }
+    }
+}
+
+/// What ended the match, sent as `event` in the result.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResultEvent {
+    Checkmate,
+    Flag,
+    /// Flag fall against a side that couldn't have mated anyway.
+    FlagUnmatable,
+    /// Each team mated on one board under `BothBoardsConsidered`.
+    Split,
+    DeadPosition,
+    Stalemate,
+    NoLegalMoves,
+    Adjudication,
+    Resignation,
+    Agreement,
+}
+
+impl ResultEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            ResultEvent::Checkmate => "checkmate",
+            ResultEvent::Flag => "flag",
+            ResultEvent::FlagUnmatable => "flag_unmatable",
+            ResultEvent::Split => "split",
+            ResultEvent::DeadPosition => "dead_position",
+            ResultEvent::Stalemate => "stalemate",
+            ResultEvent::NoLegalMoves => "no_legal_moves",
+            ResultEvent::Adjudication => "adjudication",
+            ResultEvent::Resignation => "resignation",
+            ResultEvent::Agreement => "agreement",
+        }
+    }
+}
+
+/// Outcome of the whole match. Tandem is decided by the first board to end
+/// decisively, so the board and event that caused it are kept alongside the
+/// winning team. Draws have no winner.
+pub struct GameResult {
+    pub team_winner: Option<Team>,
+    /// Board the match was decided on, i.e. where the losing side lost.
+    pub board: Option<u8>,
+    pub event: ResultEvent,
+    /// Moves each seat made with under five seconds left, per board and in
+    /// white, black order.
+    pub time_pressure_count: [[u32; 2]; 2],
+    /// Set when a draw was scored as a win for the armageddon draw-odds team.
+    pub draw_odds: bool,
+    /// Free text given by an arbiter who adjudicated the game.
+    pub reason: Option<String>,
+}
+
+impl GameResult {
+    /// Color that won on the deciding board, if there is one.
+    pub fn winner_color(&self) -> Option<Color> {
+        let (team, board) = (self.team_winner?, self.board?);
+
+        match Team::of(board, Color::White) == team {
+            true => Some(Color::White),
+            false => Some(Color::Black),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "team_winner": self.team_winner.map(Team::name),
+            "winner_color": self.winner_color().map(|color| if color == Color::White { "white" } else { "black" }),
+            "board": self.board,
+            "event": self.event.name(),
+            "draw_odds": self.draw_odds,
+            "reason": self.reason,
+            "time_pressure_count": {
+                "board_1": { "white": self.time_pressure_count[0][0], "black": self.time_pressure_count[0][1] },
+                "board_2": { "white": self.time_pressure_count[1][0], "black": self.time_pressure_count[1][1] },
+            },
+        })
+    }
+}
+
+/// A team's standing offer to draw the match. A draw ends both boards, so
+/// it needs both players of the other team to accept, and either of them
+/// moving instead declines it. One player offers for the whole team.
+pub struct DrawOffer {
+    pub team: Team,
+    /// Opponent seats that accepted, indexed by `seat_index`.
+    pub accepted: [bool; 4],
+}
+
+impl DrawOffer {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "team": self.team.name(),
+            "accepted": {
+                "board_1": { "white": self.accepted[0], "black": self.accepted[1] },
+                "board_2": { "white": self.accepted[2], "black": self.accepted[3] },
+            },
+        })
+    }
+}
+
+pub struct TandemGame {
+    pub games: [ChessGame; 2],
+    started: bool,
+    finished: bool,
+    aborted: bool,
+    result: Option<GameResult>,
+    /// Winner of each board that was mated while the match went on, only
+    /// used under `TerminationPolicy::BothBoardsConsidered`.
+    board_results: [Option<Team>; 2],
+    /// Correlation ids of the latest applied moves per seat, indexed by
+    /// `seat_index`, so a retried move isn't applied twice.
+    recent_move_ids: [VecDeque<String>; 4],
+    /// Shown to everyone, e.g. the round of an event. Kept across resets.
+    title: String,
+    /// Arbiters are building the position, see `start_setup`.
+    setup: bool,
+    /// Connection id holding each seat, indexed by `seat_index`. Kept across
+    /// resets so players don't have to sit down again.
+    seats: [Option<u64>; 4],
+    draw_offer: Option<DrawOffer>,
+    /// When the first move was made or the set up position locked.
+    started_at: Option<DateTime<Utc>>,
+    /// PGN of the game before the last reset, see `get_pgn`.
+    last_pgn: Option<String>,
+    last_sync: i64,
+    settings: GameSettings,
+    log: ActivityLog,
+}
+
+impl TandemGame {
+    pub fn new(time_control: TimeControl) -> Self {
+        let mut settings = GameSettings::new();
+        settings.time_control = time_control;
+
+        TandemGame {
+            games: [ChessGame::new(time_control), ChessGame::new(time_control)],
+            finished: false,
+            started: false,
+            aborted: false,
+            result: None,
+            board_results: [None; 2],
+            recent_move_ids: Default::default(),
+            title: String::new(),
+            setup: false,
+            seats: [None; 4],
+            draw_offer: None,
+            started_at: None,
+            last_pgn: None,
+            last_sync: 0,
+            settings,
+            log: ActivityLog::new(),
+        }
+    }
+
+    pub fn get_fen(&self, valid: bool) -> String {
+        self.state_json("update", valid).to_string()
+    }
+
+    /// State sent back to a client whose move was rejected, echoing the move
+    /// and its correlation id so pipelined moves can be told apart.
+    pub fn get_rejection(&self, tandem_move: &TandemMove, reason: &str) -> String {
+        let mut state = self.state_json("update", false);
+        let mut rejected = tandem_move.to_json();
+        rejected["reason"] = json!(reason);
+        state["rejected"] = rejected;
+
+        state.to_string()
+    }
+
+    /// Invalid update for a rejected command, with the reason attached.
+    pub fn get_error(&self, reason: &str) -> String {
+        let mut state = self.state_json("update", false);
+        state["error"] = json!(reason);
+
+        state.to_string()
+    }
+
+    /// Full state for clients that have to re-initialize, on connect or after
+    /// a reset. `reset` tells them to drop anything pending, like a selected
+    /// promotion piece.
+    pub fn get_snapshot(&self, reset: bool) -> String {
+        let mut state = self.state_json("snapshot", true);
+        state["reset"] = json!(reset);
+
+        state.to_string()
+    }
+
+    fn state_json(&self, message_type: &str, valid: bool) -> Value {
+        json!({
+            "type": message_type,
+            "valid": valid,
+            "status": self.status(),
+            "title": self.title,
+            "seats": {
+                "board_1": { "white": self.seats[0], "black": self.seats[1] },
+                "board_2": { "white": self.seats[2], "black": self.seats[3] },
+            },
+            "draw_offer": self.draw_offer.as_ref().map(DrawOffer::to_json),
+            "result": self.result.as_ref().map(|result| result.to_json()),
+            "board_results": {
+                "board_1": self.board_results[0].map(Team::name),
+                "board_2": self.board_results[1].map(Team::name),
+            },
+            "settings": self.settings.to_json(),
+            "banks": {
+                "board_1": banks_json(&self.games[0]),
+                "board_2": banks_json(&self.games[1]),
+            },
+            "board_1": self.games[0].to_string(),
+            "board_2": self.games[1].to_string(),
+        })
+    }
+
+    /// Both positions followed by both banks, one per line.
+    pub fn get_fen_pair(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n",
+            self.games[0].board,
+            self.games[1].board,
+            self.games[0].bank_string(),
+            self.games[1].bank_string(),
+        )
+    }
+
+    /// Static evaluation of both boards in centipawns, positive when white
+    /// is better.
+    pub fn get_eval(&self) -> Value {
+        json!({
+            "board_1": evaluate(&self.games[0], &self.settings.piece_values),
+            "board_2": evaluate(&self.games[1], &self.settings.piece_values),
+        })
+    }
+
+    /// Lightweight frame for clock ticks, so clients don't have to re-parse
+    /// both positions every second.
+    pub fn get_clock(&self) -> String {
+        json!({
+            "type": "clock",
+            "timestamp": Utc::now().timestamp_millis(),
+            "board_1": self.games[0].clock_json(),
+            "board_2": self.games[1].clock_json(),
+        }).to_string()
+    }
+
+    pub fn last_move_san(&self, board: u8) -> String {
+        json!({
+            "type": "san",
+            "board": board,
+            "san": self.games[(board - 1) as usize].last_move().map(|last_move| last_move.san.clone()),
+        }).to_string()
+    }
+
+    pub fn last_move(&self, board: u8) -> String {
+        json!({
+            "type": "last_move",
+            "board": board,
+            "last_move": self.games[(board - 1) as usize].last_move().map(|last_move| last_move.to_json()),
+        }).to_string()
+    }
+
+    /// Exact remaining time of one seat, synchronized up to now.
+    pub fn time_left(&mut self, board: u8, color: Color) -> String {
+        self.synchronize_time();
+
+        json!({
+            "type": "clock_query",
+            "board": board,
+            "color": if color == Color::White { "W" } else { "B" },
+            "time_ms": self.games[(board - 1) as usize].time_left(color),
+            "timestamp": Utc::now().timestamp_millis(),
+        }).to_string()
+    }
+
+    /// Whether it's the given seat's turn, for clients polling over HTTP.
+    pub fn turn_status(&self, board: u8, color: Color) -> Value {
+        let game = &self.games[(board - 1) as usize];
+        let status = if self.finished {
+            self.status()
+        } else if self.board_results[(board - 1) as usize].is_some() {
+            "board_finished"
+        } else if game.board.side_to_move() == color {
+            "turn"
+        } else {
+            "waiting"
+        };
+
+        json!({
+            "status": status,
+            "fen": game.board.to_string(),
+        })
+    }
+
+    /// PGN of both boards, one game each, for the running game or, right
+    /// after a reset, the one before it.
+    pub fn get_pgn(&self) -> String {
+        match (&self.last_pgn, self.started) {
+            (Some(v), false) => v.clone(),
+            _ => self.render_pgn(),
+        }
+    }
+
+    fn render_pgn(&self) -> String {
+        let date = self.started_at.unwrap_or_else(Utc::now).format("%Y.%m.%d").to_string();
+        let event = if self.title.is_empty() { "Tandem" } else { &self.title[..] };
+        let time_control = format!(
+            "{}+{}",
+            self.settings.time_control.base_ms / 1000,
+            self.settings.time_control.increment_ms / 1000,
+        );
+        let mut pgn = String::new();
+
+        for b_ind in 0..2 {
+            let board = (b_ind + 1) as u8;
+            let result = self.pgn_result(board);
+            let mut headers = vec![
+                ("Event", event.to_owned()),
+                ("Site", "Tandem".to_owned()),
+                ("Date", date.clone()),
+                ("Board", board.to_string()),
+                ("White", format!("Team {}", Team::of(board, Color::White).name())),
+                ("Black", format!("Team {}", Team::of(board, Color::Black).name())),
+                ("Result", result.to_owned()),
+                ("Variant", "Bughouse".to_owned()),
+                ("TimeControl", time_control.clone()),
+            ];
+
+            if let Some(fen) = self.games[b_ind].start_fen() {
+                headers.push(("SetUp", "1".to_owned()));
+                headers.push(("FEN", fen.to_owned()));
+            }
+
+            for (name, value) in headers {
+                pgn.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+            }
+
+            let movetext = self.games[b_ind].pgn_movetext(3 - board);
+            pgn.push('\n');
+            pgn.push_str(&wrap_pgn_line(format!("{} {}", movetext, result).trim_start()));
+            pgn.push_str("\n\n");
+        }
+
+        pgn
+    }
+
+    /// Result tag of one board. The match result applies to both boards,
+    /// with the winning team playing white on one and black on the other.
+    fn pgn_result(&self, board: u8) -> &'static str {
+        match &self.result {
+            _ if self.aborted => "*",
+            Some(result) => match result.team_winner {
+                Some(team) if Team::of(board, Color::White) == team => "1-0",
+                Some(_) => "0-1",
+                None => "1/2-1/2",
+            },
+            None => "*",
+        }
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.aborted {
+            "aborted"
+        } else if self.result.as_ref().is_some_and(|result| result.team_winner.is_none()) {
+            "drawn"
+        } else if self.finished {
+            "finished"
+        } else if self.started {
+            "playing"
+        } else if self.setup {
+            "setup"
+        } else {
+            "waiting"
+        }
+    }
+
+    pub fn should_update(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        self.synchronize_time();
+
+        self.games[0].should_update() 
+        || self.games[1].should_update()
+    }
+
+    pub fn reset(&mut self) {
+        if self.started {
+            self.last_pgn = Some(self.render_pgn());
+        }
+
+        for i in 0..2 {
+            self.games[i] = ChessGame::new(self.settings.time_control);
+        }
+
+        self.started = false;
+        self.finished = false;
+        self.aborted = false;
+        self.result = None;
+        self.board_results = [None; 2];
+        self.recent_move_ids = Default::default();
+        self.setup = false;
+        self.draw_offer = None;
+        self.started_at = None;
+        self.last_sync = 0;
+
+        self.log.record("reset", json!({}));
+    }
+
+    /// Ends a game nobody has moved in yet. Once the first move is made the
+    /// game can no longer be aborted.
+    pub fn abort(&mut self) -> bool {
+        if self.started || self.finished {
+            return false;
+        }
+
+        self.finished = true;
+        self.aborted = true;
+
+        self.log.record("abort", json!({}));
+
+        true
+    }
+
+    /// Settings can only be changed before the first move.
+    pub fn change_setting(&mut self, key: &str, value: &str) -> bool {
+        if self.started || self.finished {
+            return false;
+        }
+
+        if !self.settings.set(key, value) {
+            return false;
+        }
+
+        if key == "time_control" || key == "increment_ms" {
+            for game in self.games.iter_mut() {
+                game.set_time_control(self.settings.time_control);
+            }
+        }
+
+        self.log.record("setting", json!({ "key": key, "value": value }));
+
+        true
+    }
+
+    /// Arbiter correction of one board, leaving the clocks and the other board
+    /// alone. Besides parsing, the position has to be playable as tandem:
+    /// at most 16 pieces a side, no pawns on the back ranks and not already
+    /// over. Wildly uneven material is accepted but flagged in the log.
+    pub fn set_position(&mut self, board: &str, fen: &str, banks: &str) -> Result<(), &'static str> {
+        let b_ind = match board {
+            "1" => 0,
+            "2" => 1,
+            _ => return Err("invalid_board"),
+        };
+
+        let new_board = match Board::from_str(fen) {
+            Ok(v) => v,
+            Err(_) => return Err("invalid_fen"),
+        };
+
+        let banks_parsed = match parse_bank_string(banks) {
+            Some(v) => v,
+            None => return Err("invalid_banks"),
+        };
+
+        validate_position(&new_board)?;
+
+        // The chess crate drops the move counters, so they're read from the
+        // FEN directly, defaulting like a fresh game.
+        let mut counters = fen.split_whitespace().skip(4).map(|v| v.parse::<u32>().ok());
+        let halfmove = counters.next().flatten().unwrap_or(0);
+        let move_number = counters.next().flatten().unwrap_or(1).max(1);
+
+        self.games[b_ind].set_position(new_board, banks_parsed, (halfmove, move_number));
+
+        let material = evaluate_material(&self.games[b_ind], &self.settings.piece_values);
+
+        self.log.record("setfen", json!({
+            "board": b_ind + 1,
+            "fen": fen,
+            "banks": banks,
+            "imbalanced": material.abs() > IMBALANCED_MATERIAL,
+        }));
+
+        Ok(())
+    }
+
+    /// Sets the title, rejecting anything too long or containing control
+    /// characters.
+    pub fn set_title(&mut self, title: &str) -> bool {
+        if title.chars().count() > MAX_TITLE_LENGTH || title.chars().any(char::is_control) {
+            return false;
+        }
+
+        self.title = title.to_owned();
+        self.log.record("title", json!({ "title": title }));
+
+        true
+    }
+
+    /// Seats a client, freeing any other seat it held. A seat held by
+    /// someone else has to be freed first.
+    pub fn sit(&mut self, client_id: u64, board: u8, color: Color) -> Result<(), &'static str> {
+        let seat = seat_index(board, color);
+
+        if self.seats[seat].is_some_and(|id| id != client_id) {
+            return Err("seat_taken");
+        }
+
+        let _ = self.stand(client_id);
+        self.seats[seat] = Some(client_id);
+        self.log.record("sit", json!({
+            "id": client_id,
+            "board": board,
+            "color": if color == Color::White { "W" } else { "B" },
+        }));
+
+        Ok(())
+    }
+
+    /// Frees the seat of a client. Returns false if it wasn't seated.
+    pub fn stand(&mut self, client_id: u64) -> bool {
+        let seat = match self.seats.iter().position(|id| *id == Some(client_id)) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        self.seats[seat] = None;
+        self.log.record("stand", json!({ "id": client_id }));
+
+        true
+    }
+
+    /// Enters setup mode, where arbiters build the starting position with
+    /// `place` and no moves are accepted until `lock`. Only before the game.
+    pub fn start_setup(&mut self) -> bool {
+        if self.started || self.finished {
+            return false;
+        }
+
+        self.setup = true;
+        self.log.record("setup", json!({}));
+
+        true
+    }
+
+    /// Puts a piece like `wN` on a square, or clears it with `empty`. Only
+    /// the basics are checked here, the full position is validated on lock.
+    pub fn place(&mut self, board: &str, square: &str, piece: &str) -> Result<(), &'static str> {
+        if !self.setup {
+            return Err("not_in_setup");
+        }
+
+        let b_ind = match board {
+            "1" => 0,
+            "2" => 1,
+            _ => return Err("invalid_board"),
+        };
+
+        let square = match Square::from_str(&square_name(square)).ok() {
+            Some(v) => v,
+            None => return Err("invalid_square"),
+        };
+
+        let mut board_builder = BoardBuilder::from(&self.games[b_ind].board);
+
+        if piece == "empty" {
+            board_builder.clear_square(square);
+        } else {
+            let (piece_parsed, color) = match parse_piece(piece) {
+                Some(v) => v,
+                None => return Err("invalid_piece"),
+            };
+
+            // There is only one king a side, so placing it moves it.
+            if piece_parsed == Piece::King {
+                board_builder.clear_square(self.games[b_ind].board.king_square(color));
+            }
+
+            board_builder.piece(square, piece_parsed, color);
+        }
+
+        // The chess crate can't even check a board without both kings.
+        for color in [Color::White, Color::Black] {
+            let kings = ALL_SQUARES.iter().filter(|square| board_builder[**square] == Some((Piece::King, color))).count();
+
+            if kings != 1 {
+                return Err("invalid_position");
+            }
+
+            board_builder.castle_rights(color, placed_castle_rights(&board_builder, color));
+        }
+
+        let new_board = match Board::try_from(board_builder) {
+            Ok(v) => v,
+            Err(_) => return Err("invalid_position"),
+        };
+
+        if [Color::White, Color::Black].iter().any(|color| new_board.color_combined(*color).popcnt() > 16) {
+            return Err("too_many_pieces");
+        }
+
+        if (new_board.pieces(Piece::Pawn) & (get_rank(Rank::First) | get_rank(Rank::Eighth))).popcnt() > 0 {
+            return Err("pawn_on_back_rank");
+        }
+
+        self.games[b_ind].board = new_board;
+        self.log.record("place", json!({ "board": b_ind + 1, "square": square.to_string(), "piece": piece }));
+
+        Ok(())
+    }
+
+    /// Leaves setup mode once both positions are playable and starts the
+    /// game, clocks included.
+    pub fn lock(&mut self) -> Result<(), &'static str> {
+        if !self.setup {
+            return Err("not_in_setup");
+        }
+
+        for game in self.games.iter() {
+            validate_position(&game.board)?;
+        }
+
+        for game in self.games.iter_mut() {
+            game.restart_history();
+        }
+
+        self.setup = false;
+        self.started = true;
+        self.started_at = Some(Utc::now());
+        self.last_sync = Utc::now().timestamp_millis();
+        self.log.record("lock", json!({}));
+
+        Ok(())
+    }
+
+    /// Offers a draw for the team of the given seat. Offering while the
+    /// other team's offer stands accepts that one instead.
+    pub fn offer_draw(&mut self, board: u8, color: Color) -> Result<(), &'static str> {
+        if self.finished {
+            return Err("game_finished");
+        }
+
+        if !self.started {
+            return Err("not_started");
+        }
+
+        let team = Team::of(board, color);
+
+        match &self.draw_offer {
+            Some(offer) if offer.team == team => Err("draw_offer_pending"),
+            Some(_) => self.accept_draw(board, color),
+            None => {
+                self.draw_offer = Some(DrawOffer { team, accepted: [false; 4] });
+                self.log.record("draw_offer", json!({ "team": team.name() }));
+
+                Ok(())
+            },
+        }
+    }
+
+    /// Accepts the other team's draw offer for one seat, drawing the match
+    /// once both of that team's opponents accepted.
+    pub fn accept_draw(&mut self, board: u8, color: Color) -> Result<(), &'static str> {
+        let team = Team::of(board, color);
+
+        let offer = match &mut self.draw_offer {
+            Some(v) if v.team != team => v,
+            _ => return Err("no_draw_offer"),
+        };
+
+        offer.accepted[seat_index(board, color)] = true;
+        self.log.record("draw_accept", json!({
+            "board": board,
+            "color": if color == Color::White { "W" } else { "B" },
+        }));
+
+        let accepted = [(1, Color::White), (1, Color::Black), (2, Color::White), (2, Color::Black)]
+            .into_iter()
+            .filter(|(b, c)| Team::of(*b, *c) == team)
+            .all(|(b, c)| offer.accepted[seat_index(b, c)]);
+
+        if accepted {
+            self.finish(None, None, ResultEvent::Agreement);
+        }
+
+        Ok(())
+    }
+
+    /// Gives up the match for the team of the given seat. Only while the game
+    /// is running.
+    pub fn resign(&mut self, board: u8, color: Color) -> Result<(), &'static str> {
+        if self.finished {
+            return Err("game_finished");
+        }
+
+        if !self.started {
+            return Err("not_started");
+        }
+
+        self.synchronize_time();
+
+        // The clocks may have just ended the game.
+        if self.finished {
+            return Err("game_finished");
+        }
+
+        self.finish(Some(Team::of(board, color).opponent()), Some(board), ResultEvent::Resignation);
+
+        Ok(())
+    }
+
+    pub fn berserk(&mut self, board: u8, color: Color) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        self.synchronize_time();
+
+        if !self.games[(board - 1) as usize].berserk(color) {
+            return false;
+        }
+
+        self.log.record("berserk", json!({
+            "board": board,
+            "color": if color == Color::White { "W" } else { "B" },
+        }));
+
+        true
+    }
+
+    /// Warning frame for the team of the given move if, with the setting on,
+    /// the opponent on the partner board is to move and has a mate in one.
+    pub fn mate_warning(&self, tandem_move: &TandemMove) -> Option<String> {
+        if !self.settings.mate_warning || self.finished {
+            return None;
+        }
+
+        let partner_board = 3 - tandem_move.board;
+        let board = &self.games[(partner_board - 1) as usize].board;
+
+        if board.side_to_move() != tandem_move.color || !has_mate_in_one(board) {
+            return None;
+        }
+
+        Some(json!({
+            "type": "warning",
+            "warning": "partner_mate_threat",
+            "board": partner_board,
+            "team": Team::of(tandem_move.board, tandem_move.color).name(),
+        }).to_string())
+    }
+
+    pub fn log_event(&mut self, event: &str, details: Value) {
+        self.log.record(event, details);
+    }
+
+    pub fn get_log(&self) -> Value {
+        self.log.to_json()
+    }
+
+    /// Charges the time since the last sync to whoever is on move. Before the
+    /// game starts this only moves the baseline, so the first move's sync
+    /// marks the exact moment the opponent's clock starts. A move syncs, flips
+    /// the turn and credits increments under one lock, so no time is counted
+    /// twice or lost at the handoff.
+    pub fn synchronize_time(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        let now = Utc::now().timestamp_millis();
+
+        if !self.started {
+            self.last_sync = now;
+            return;
+        }
+
+        let time_dif = (now - self.last_sync).max(0);
+        self.last_sync = now;
+
+        for i in 0..2 {
+            if self.board_results[i].is_some() {
+                continue;
+            }
+
+            self.games[i].synchronize_time(time_dif, self.settings.free_moves);
+
+            if let Some(color) = self.games[i].flagged() && !self.finished {
+                let board = (i + 1) as u8;
+
+                if self.settings.flag == FlagPolicy::DrawIfUnmatable && !can_mate(&self.games[i], !color) {
+                    self.finish(None, Some(board), ResultEvent::FlagUnmatable);
+                } else {
+                    self.finish(Some(Team::of(board, color).opponent()), Some(board), ResultEvent::Flag);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, team_winner: Option<Team>, board: Option<u8>, event: ResultEvent) {
+        let draw_odds = team_winner.is_none() && self.settings.draw_odds.is_some();
+        let team_winner = team_winner.or(self.settings.draw_odds);
+        let time_pressure_count = self.time_pressure_counts();
+
+        self.end(GameResult { team_winner, board, event, time_pressure_count, draw_odds, reason: None });
+    }
+
+    fn end(&mut self, result: GameResult) {
+        self.log.record("game_over", result.to_json());
+
+        self.finished = true;
+        self.draw_offer = None;
+        self.result = Some(result);
+    }
+
+    fn time_pressure_counts(&self) -> [[u32; 2]; 2] {
+        [0, 1].map(|i| [
+            self.games[i].time_pressure_count(Color::White),
+            self.games[i].time_pressure_count(Color::Black),
+        ])
+    }
+
+    /// Arbiter adjudication of a running or finished game, e.g. `A`, `B` or
+    /// `draw`. Replaces any earlier result and is taken as given, so draw
+    /// odds don't apply.
+    pub fn set_result(&mut self, outcome: &str, reason: &str) -> Result<(), &'static str> {
+        if self.aborted || (!self.started && !self.finished) {
+            return Err("no_game");
+        }
+
+        let team_winner = match outcome {
+            "A" => Some(Team::A),
+            "B" => Some(Team::B),
+            "draw" => None,
+            _ => return Err("invalid_outcome"),
+        };
+
+        if reason.chars().count() > MAX_TITLE_LENGTH || reason.chars().any(char::is_control) {
+            return Err("invalid_reason");
+        }
+
+        self.synchronize_time();
+
+        let time_pressure_count = self.time_pressure_counts();
+
+        self.end(GameResult {
+            team_winner,
+            board: None,
+            event: ResultEvent::Adjudication,
+            time_pressure_count,
+            draw_odds: false,
+            reason: Some(reason.to_owned()),
+        });
+
+        Ok(())
+    }
+
+    /// Ends the match on a checkmate, or under `BothBoardsConsidered` only the
+    /// mated board until the other one is mated as well. Split boards are a
+    /// drawn match.
+    fn board_mated(&mut self, b_ind: usize, team: Team) {
+        let board = (b_ind + 1) as u8;
+
+        if self.settings.termination == TerminationPolicy::FirstBoardDecides {
+            self.finish(Some(team), Some(board), ResultEvent::Checkmate);
+            return;
+        }
+
+        self.board_results[b_ind] = Some(team);
+        self.log.record("board_over", json!({ "board": board, "team_winner": team.name() }));
+
+        match self.board_results {
+            [Some(a), Some(b)] if a == b => self.finish(Some(a), None, ResultEvent::Checkmate),
+            [Some(_), Some(_)] => self.finish(None, None, ResultEvent::Split),
+            _ => (),
+        };
+    }
+
+    pub fn move_piece(&mut self, tandem_move: &TandemMove) -> Result<(), &'static str> {
+        println!("{:?}", tandem_move);
+
+        // Checked ahead of `try_move` as the seat index depends on it.
+        if tandem_move.board != 1 && tandem_move.board != 2 {
+            self.log.record("move_rejected", json!({
+                "move": tandem_move.to_json(),
+                "reason": "invalid_board",
+            }));
+
+            return Err("invalid_board");
+        }
+
+        let seat = seat_index(tandem_move.board, tandem_move.color);
+
+        // A retried move is acknowledged with the current state instead of
+        // being applied a second time.
+        if let Some(id) = &tandem_move.id && self.recent_move_ids[seat].contains(id) {
+            self.log.record("move_duplicate", tandem_move.to_json());
+
+            return Ok(());
+        }
+
+        let material_before = cfg!(debug_assertions).then(|| self.material_totals());
+        let result = self.try_move(tandem_move);
+
+        if result.is_ok() && let Some(material_before) = material_before {
+            self.check_conservation(material_before, tandem_move);
+        }
+
+        // Moving instead of accepting declines the other team's offer.
+        if result.is_ok()
+        && let Some(team) = self.draw_offer.as_ref().map(|offer| offer.team)
+        && team != Team::of(tandem_move.board, tandem_move.color) {
+            self.draw_offer = None;
+            self.log.record("draw_declined", json!({ "team": team.name() }));
+        }
+
+        if result.is_ok() && let Some(id) = &tandem_move.id {
+            if self.recent_move_ids[seat].len() >= RECENT_MOVE_IDS {
+                let _ = self.recent_move_ids[seat].pop_front();
+            }
+
+            self.recent_move_ids[seat].push_back(id.clone());
+        }
+
+        match result {
+            Ok(_) => self.log.record("move", tandem_move.to_json()),
+            Err(reason) => self.log.record("move_rejected", json!({
+                "move": tandem_move.to_json(),
+                "reason": reason,
+            })),
+        };
+
+        result
+    }
+
+    /// Pieces of each color and type across both boards and all banks, in
+    /// bank order. Captures and promotions only move material around, so
+    /// these totals never change with a move.
+    fn material_totals(&self) -> [[i32; 5]; 2] {
+        let mut totals = [[0; 5]; 2];
+
+        for game in self.games.iter() {
+            for (color, sp_array) in [(Color::White, &game.white_sp), (Color::Black, &game.black_sp)] {
+                let pieces = game.board.color_combined(color);
+
+                for (i, piece) in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn].into_iter().enumerate() {
+                    totals[color.to_index()][i] += (game.board.pieces(piece) & pieces).popcnt() as i32 + sp_array[i];
+                }
+            }
+        }
+
+        totals
+    }
+
+    /// Debug build self-check catching accounting bugs in the capture, drop
+    /// and promotion paths. Auto-queen creates material and a lowered bank
+    /// cap discards it, so the check is skipped when either is in play.
+    fn check_conservation(&mut self, material_before: [[i32; 5]; 2], tandem_move: &TandemMove) {
+        if self.settings.auto_queen || self.settings.bank_cap != GameSettings::new().bank_cap {
+            return;
+        }
+
+        let material_after = self.material_totals();
+
+        if material_before != material_after {
+            println!("Material not conserved by {:?}: {:?} -> {:?}", tandem_move, material_before, material_after);
+
+            self.log.record("conservation_violation", json!({
+                "move": tandem_move.to_json(),
+                "before": material_before,
+                "after": material_after,
+            }));
+        }
+    }
+
+    fn try_move(&mut self, tandem_move: &TandemMove) -> Result<(), &'static str> {
+        self.synchronize_time();
+
+        if self.finished {
+            return Err("game_finished");
+        }
+
+        if self.setup {
+            return Err("setup_mode");
+        }
+
+        if tandem_move.board != 1 && tandem_move.board != 2 {
+            return Err("invalid_board");
+        }
+
+        let b_ind = (tandem_move.board - 1) as usize;
+        let o_ind = (b_ind + 1) % 2;
+
+        if self.board_results[b_ind].is_some() {
+            return Err("board_finished");
+        }
+
+        // The boards keep independent turn orders: whose turn it is comes only
+        // from the moved-on board, and the partner board is only touched for
+        // banks and promotions, never for its side to move.
+        let board = self.games[b_ind].board;
+        let other_board = self.games[o_ind].board;
+
+        if board.side_to_move() != tandem_move.color {
+            return Err("wrong_turn");
+        }
+
+        let target = match Square::from_str(&tandem_move.target).ok() {
+            Some(v) => v,
+            None => return Err("invalid_square"),
+        };
+
+        if let Some(Piece::King) = board.piece_on(target) {
+            println!("Tried to capture King");
+            return Err("king_capture");
+        }
+
+        if tandem_move.source == "spare" {
+            let (piece, color) = match parse_piece(&tandem_move.piece) {
+                Some((Piece::King, _)) | None => return Err("invalid_piece"),
+                Some(v) => v,
+            };
+
+            // Only the side to move drops, and only from its own bank.
+            if color != tandem_move.color {
+                return Err("invalid_piece");
+            }
+
+            if self.games[b_ind].bank_count(color, piece) <= 0 {
+                return Err("empty_bank");
+            }
+
+            let board_new = set_piece_on_board(&board, piece, color, target)?;
+
+            // Everything is validated by now, so the bank is only touched
+            // together with the board.
+            let _ = self.games[b_ind].decrease_count(&color, piece);
+            self.games[b_ind].board = board_new;
+            let increment = self.games[b_ind].change_turn(LastMove {
+                source: tandem_move.source.clone(),
+                target: tandem_move.target.clone(),
+                piece,
+                color,
+                capture: None,
+                promotion: None,
+                drop: true,
+                san: drop_san(&board_new, piece, target),
+            }, &self.settings.decaying_increment);
+            self.credit_increment(b_ind, tandem_move.color, increment);
+            self.after_move(b_ind, tandem_move.color);
+
+            self.started_at.get_or_insert_with(Utc::now);
+            self.started = true;
+            return Ok(());
+        }
+
+        let source = match Square::from_str(&tandem_move.source).ok() {
+            Some(v) => v,
+            None => return Err("invalid_square"),
+        };
+        let piece_source = match board.piece_on(source) {
+            Some(v) => v,
+            None => return Err("no_piece"),
+        };
+        let rank = target.get_rank() as u8;
+        let is_promotion = piece_source == Piece::Pawn && (rank == 0 || rank == 7);
+
+        let promotion_target_op = Square::from_str(&tandem_move.promotion).ok();
+        let mut promotion_piece_op = None;
+
+        // Without a partner square to take the piece from, auto-queen promotes
+        // like standard chess and leaves the partner board alone.
+        let auto_queen = is_promotion && promotion_target_op.is_none() && self.settings.auto_queen;
+
+        if auto_queen {
+            promotion_piece_op = Some(Piece::Queen);
+        }
+
+        // Checked before legality, a pawn move to the last rank without a
+        // promotion piece never passes the legality check.
+        if is_promotion && !auto_queen && promotion_target_op.is_none() {
+            return Err("missing_promotion");
+        }
+
+        if is_promotion && let Some(v) = promotion_target_op {
+            promotion_piece_op = other_board.piece_on(v);
+
+            match other_board.color_on(v) {
+                Some(v) => {
+                    if v != tandem_move.color {
+                        return Err("invalid_promotion");
+                    }
+                },
+                None => return Err("invalid_promotion"),
+            };
+
+            // An empty or pawn/king square would promote to nothing while
+            // still handing the partner a pawn, desyncing the banks.
+            match promotion_piece_op {
+                Some(Piece::Queen | Piece::Rook | Piece::Bishop | Piece::Knight) => (),
+                _ => return Err("invalid_promotion"),
+            };
+        }
+
+        let chess_move = ChessMove::new(source, target, promotion_piece_op);
+
+        if !board.legal(chess_move) {
+            return Err("illegal_move");
+        }
+
+        if is_promotion && !auto_queen && let Some(promotion_target) = promotion_target_op {
+            println!("Checking Promotion valid");
+
+            let bit_board = other_board.pinned();
+            let square_board = BitBoard::from_square(promotion_target);
+
+            if bit_board.bitand(square_board).popcnt() > 0 {
+                return Err("invalid_promotion");
+            }
+
+            let mut board_builder = BoardBuilder::from(&other_board);
+            board_builder.clear_square(promotion_target);
+
+            let board_other = match Board::try_from(board_builder) {
+                Ok(v) => v,
+                Err(_) => return Err("invalid_promotion"),
+            };
+
+            self.games[o_ind].board = board_other;
+            self.games[o_ind].add_pawn(&tandem_move.color, &self.settings.bank_cap());
+        }
+
+        // A capturing promotion like g7xh8 has two separate effects on the
+        // partner board: the promoted piece was taken off it above, and the
+        // captured piece is credited to its bank here. The capture is read
+        // from the pre-move copy of this board, so neither effect can see or
+        // repeat the other.
+        //
+        // En passant lands on an empty square but still takes a pawn.
+        let is_en_passant = piece_source == Piece::Pawn && source.get_file() != target.get_file();
+        let capture = match board.piece_on(target) {
+            None if is_en_passant => Some(Piece::Pawn),
+            v => v,
+        };
+
+        if let Some(v) = capture {
+            self.games[o_ind].add_piece(&tandem_move.color, v, &self.settings.bank_cap());
+        }
+
+        println!("{:?} {:?}", source, target);
+        let board_new = board.make_move_new(chess_move);
+        let mate = is_mate(&board_new, piece_source, target, tandem_move.color);
+
+        let increment = self.games[b_ind].change_turn(LastMove {
+            source: tandem_move.source.clone(),
+            target: tandem_move.target.clone(),
+            piece: piece_source,
+            color: tandem_move.color,
+            capture,
+            promotion: promotion_piece_op,
+            drop: false,
+            san: move_san(&board, chess_move, &board_new, capture.is_some(), mate),
+        }, &self.settings.decaying_increment);
+        self.credit_increment(b_ind, tandem_move.color, increment);
+        self.games[b_ind].board = board_new;
+
+        if mate {
+            self.board_mated(b_ind, Team::of(tandem_move.board, tandem_move.color));
+        }
+
+        self.after_move(b_ind, tandem_move.color);
+
+        self.started_at.get_or_insert_with(Utc::now);
+        self.started = true;
+        Ok(())
+    }
+
+    /// Credits a move's increment to the mover, or to the partner (same team,
+    /// other board, other color) under the cooperative rule.
+    fn credit_increment(&mut self, b_ind: usize, color: Color, increment: i64) {
+        if increment == 0 {
+            return;
+        }
+
+        if self.settings.increment_to_partner {
+            self.games[(b_ind + 1) % 2].add_increment(!color, increment);
+        } else {
+            self.games[b_ind].add_increment(color, increment);
+        }
+    }
+
+    fn after_move(&mut self, b_ind: usize, color: Color) {
+        self.apply_check_bonus(b_ind, color);
+
+        if !self.finished && self.settings.dead_position_draw && self.is_dead_position() {
+            self.finish(None, None, ResultEvent::DeadPosition);
+        }
+
+        if !self.finished && self.is_stuck(b_ind) {
+            let board = (b_ind + 1) as u8;
+
+            match self.settings.stalemate {
+                StalematePolicy::Draw => self.finish(None, Some(board), ResultEvent::Stalemate),
+                StalematePolicy::LossOnMove => self.finish(Some(Team::of(board, color)), Some(board), ResultEvent::NoLegalMoves),
+            };
+        }
+    }
+
+    /// Whether the side to move on a board has neither a legal move nor a
+    /// legal drop from its bank, without being in check.
+    fn is_stuck(&self, b_ind: usize) -> bool {
+        let game = &self.games[b_ind];
+        let board = &game.board;
+        let color = board.side_to_move();
+
+        if board.checkers().popcnt() > 0 || MoveGen::new_legal(board).len() > 0 {
+            return false;
+        }
+
+        ![Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn].into_iter()
+            .any(|piece| game.bank_count(color, piece) > 0 && is_droppable(board, piece, color))
+    }
+
+    /// Conservative dead position check: with both banks empty, no pawns
+    /// left to promote and at most a single minor piece across both boards,
+    /// no capture or drop can ever bring mating material back.
+    fn is_dead_position(&self) -> bool {
+        let mut pieces = 0;
+        let mut minor_pieces = 0;
+
+        for game in self.games.iter() {
+            let board = &game.board;
+
+            if !game.bank_empty() || board.pieces(Piece::Pawn).popcnt() > 0 {
+                return false;
+            }
+
+            pieces += (board.combined() & !board.pieces(Piece::King)).popcnt();
+            minor_pieces += (board.pieces(Piece::Bishop) | board.pieces(Piece::Knight)).popcnt();
+        }
+
+        pieces == 0 || (pieces == 1 && minor_pieces == 1)
+    }
+
+    fn apply_check_bonus(&mut self, b_ind: usize, color: Color) {
+        if self.settings.check_bonus_ms == 0 {
+            return;
+        }
+
+        if self.games[b_ind].board.checkers().popcnt() > 0 {
+            self.games[b_ind].add_time(color, self.settings.check_bonus_ms);
+        }
+    }
+}
+
+/// The board after dropping a spare piece, or why the drop is illegal.
+/// Drops follow the usual check rules, so a drop while in check has to
+/// block it.
+fn set_piece_on_board(board: &Board, piece: Piece, color: Color, target: Square) -> Result<Board, &'static str> {
+    let target_x = target.get_rank() as i32;
+
+    if (target_x == 7 || target_x == 0) && piece == Piece::Pawn {
+        return Err("pawn_on_back_rank");
+    }
+
+    if board.piece_on(target).is_some() {
+        return Err("square_occupied");
+    }
+
+    let mut board_builder = BoardBuilder::from(board);
+    board_builder.piece(target, piece, color);
+    // A drop is a move, so an en passant right from the last double push is
+    // gone. Kept, the file would be read for the wrong side.
+    board_builder.en_passant(None);
+
+    match color {
+        Color::White => board_builder.side_to_move(Color::Black),
+        _ => board_builder.side_to_move(Color::White),
+    };
+
+    // The only way a drop fails validation is by leaving the dropper's king
+    // attacked, anything else means the position itself is broken.
+    let new_board = match Board::try_from(board_builder) {
+        Ok(v) => v,
+        Err(_) if board.checkers().popcnt() > 0 => return Err("in_check"),
+        Err(_) => return Err("invalid_position"),
+    };
+
+    if is_mate(&new_board, piece, target, color) {
+        Err("illegal_drop")
+    } else {
+        Ok(new_board)
+    }
+}
+
+/// Breaks PGN movetext into lines of at most 80 characters.
+fn wrap_pgn_line(text: &str) -> String {
+    let mut lines = vec![String::new()];
+
+    for token in text.split(' ') {
+        let line = lines.last_mut().unwrap();
+
+        if !line.is_empty() && line.len() + 1 + token.len() > 80 {
+            lines.push(token.to_owned());
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+
+            line.push_str(token);
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Standard algebraic notation of a board move, e.g. `Nbd2`, `exd5` or
+/// `e8=Q+`. `#` follows the house rule, so a check that could still be
+/// blocked by a drop only gets a `+`.
+fn move_san(board: &Board, chess_move: ChessMove, board_new: &Board, capture: bool, mate: bool) -> String {
+    let source = chess_move.get_source();
+    let target = chess_move.get_dest();
+    let piece = board.piece_on(source).unwrap_or(Piece::Pawn);
+    let files = source.get_file().to_index() as i32 - target.get_file().to_index() as i32;
+
+    let mut san = if piece == Piece::King && files.abs() == 2 {
+        if files < 0 { "O-O".to_owned() } else { "O-O-O".to_owned() }
+    } else if piece == Piece::Pawn {
+        let mut san = String::new();
+
+        if capture {
+            san.push_str(&source.to_string()[..1]);
+            san.push('x');
+        }
+
+        san.push_str(&target.to_string());
+
+        if let Some(v) = chess_move.get_promotion() {
+            san.push('=');
+            san.push_str(&v.to_string(Color::White));
+        }
+
+        san
+    } else {
+        let mut san = piece.to_string(Color::White);
+        let others = MoveGen::new_legal(board)
+            .filter(|other| other.get_dest() == target && other.get_source() != source
+                && board.piece_on(other.get_source()) == Some(piece))
+            .map(|other| other.get_source())
+            .collect::<Vec<Square>>();
+
+        if !others.is_empty() {
+            let same_file = others.iter().any(|other| other.get_file() == source.get_file());
+            let same_rank = others.iter().any(|other| other.get_rank() == source.get_rank());
+            let source_name = source.to_string();
+
+            if !same_file {
+                san.push_str(&source_name[..1]);
+            } else if !same_rank {
+                san.push_str(&source_name[1..]);
+            } else {
+                san.push_str(&source_name);
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+
+        san.push_str(&target.to_string());
+        san
+    };
+
+    san.push_str(check_suffix(board_new, mate));
+    san
+}
+
+/// Drop notation, e.g. `N@e6+`. Drops can't mate under the house rules.
+fn drop_san(board_new: &Board, piece: Piece, target: Square) -> String {
+    format!("{}@{}{}", piece.to_string(Color::White), target, check_suffix(board_new, false))
+}
+
+fn check_suffix(board: &Board, mate: bool) -> &'static str {
+    if mate {
+        "#"
+    } else if board.checkers().popcnt() > 0 {
+        "+"
+    } else {
+        ""
+    }
+}
+
+/// Castling rights that still fit after pieces were placed by hand: kept
+/// only while the king and the matching rook are on their home squares.
+fn placed_castle_rights(board_builder: &BoardBuilder, color: Color) -> CastleRights {
+    let rank = color.to_my_backrank();
+    let rights = board_builder.get_castle_rights(color);
+    let home = |file: File, piece: Piece| board_builder[Square::make_square(rank, file)] == Some((piece, color));
+
+    if !home(File::E, Piece::King) {
+        return CastleRights::NoRights;
+    }
+
+    let mut kept = CastleRights::NoRights;
+
+    if rights.has_kingside() && home(File::H, Piece::Rook) {
+        kept = kept.add(CastleRights::KingSide);
+    }
+
+    if rights.has_queenside() && home(File::A, Piece::Rook) {
+        kept = kept.add(CastleRights::QueenSide);
+    }
+
+    kept
+}
+
+/// Parses piece names as the web client uses them, e.g. `wN` or `bP`.
+fn parse_piece(piece: &str) -> Option<(Piece, Color)> {
+    let chars = piece.as_bytes();
+
+    if chars.len() != 2 {
+        return None;
+    }
+
+    let color = match chars[0] as char {
+        'w' => Color::White,
+        'b' => Color::Black,
+        _ => return None,
+    };
+
+    let piece = match chars[1] as char {
+        'P' => Piece::Pawn,
+        'N' => Piece::Knight,
+        'B' => Piece::Bishop,
+        'R' => Piece::Rook,
+        'Q' => Piece::Queen,
+        'K' => Piece::King,
+        _ => return None,
+    };
+
+    Some((piece, color))
+}
+
+/// Checks a position is playable as tandem: at most 16 pieces a side, no
+/// pawns on the back ranks and not already over.
+fn validate_position(board: &Board) -> Result<(), &'static str> {
+    if [Color::White, Color::Black].iter().any(|color| board.color_combined(*color).popcnt() > 16) {
+        return Err("too_many_pieces");
+    }
+
+    if (board.pieces(Piece::Pawn) & (get_rank(Rank::First) | get_rank(Rank::Eighth))).popcnt() > 0 {
+        return Err("pawn_on_back_rank");
+    }
+
+    if board.status() != BoardStatus::Ongoing {
+        return Err("terminal_position");
+    }
+
+    Ok(())
+}
+
+/// Whether a side still has mating material on a board: anything in its
+/// bank, a pawn, rook or queen, or at least two minor pieces.
+fn can_mate(game: &ChessGame, color: Color) -> bool {
+    let board = &game.board;
+    let pieces = board.color_combined(color);
+    let heavy = (board.pieces(Piece::Pawn) | board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) & pieces;
+    let minor = (board.pieces(Piece::Bishop) | board.pieces(Piece::Knight)) & pieces;
+    let bank = match color {
+        Color::White => &game.white_sp,
+        _ => &game.black_sp,
+    };
+
+    bank.iter().any(|count| *count > 0) || heavy.popcnt() > 0 || minor.popcnt() >= 2
+}
+
+/// Whether the side to move can mate right away. Drops are left out because
+/// the house rules don't allow mating by a drop.
+fn has_mate_in_one(board: &Board) -> bool {
+    let color = board.side_to_move();
+
+    MoveGen::new_legal(board).any(|chess_move| {
+        let piece = match board.piece_on(chess_move.get_source()) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        is_mate(&board.make_move_new(chess_move), piece, chess_move.get_dest(), color)
+    })
+}
+
+/// Whether a spare piece has at least one empty square it could legally be
+/// dropped on, using the same rules as an actual drop.
+fn is_droppable(board: &Board, piece: Piece, color: Color) -> bool {
+    let empty = !board.combined();
+
+    empty.into_iter().any(|square| set_piece_on_board(board, piece, color, square).is_ok())
+}
+
+/// Bank counts per color and piece letter, each with whether that piece can
+/// currently be dropped anywhere.
+fn banks_json(game: &ChessGame) -> Value {
+    let mut banks = serde_json::Map::new();
+
+    for (color, sp_array) in [(Color::White, &game.white_sp), (Color::Black, &game.black_sp)] {
+        let mut bank = serde_json::Map::new();
+
+        for (count, piece) in sp_array.iter().zip([Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn]) {
+            bank.insert(piece.to_string(Color::White), json!({
+                "count": count,
+                "droppable": *count > 0 && is_droppable(&game.board, piece, color),
+            }));
+        }
+
+        banks.insert(if color == Color::White { "white" } else { "black" }.to_owned(), Value::Object(bank));
+    }
+
+    Value::Object(banks)
+}
+
+fn is_mate(board: &Board, piece: Piece, target: Square, color: Color) -> bool {
+    let target_x = target.get_rank() as i32;
+    let target_y = target.get_file() as i32;
+
+    let square_king = match color {
+        Color::White => board.king_square(Color::Black),
+        _ => board.king_square(Color::White),
+    };
+
+    let king_x = square_king.get_rank() as i32;
+    let king_y = square_king.get_file() as i32;
+
+    let close_chess = (king_x - target_x).abs().max((king_y - target_y).abs()) <= 1;
+
+    board.status() == BoardStatus::Checkmate && (close_chess || piece == Piece::Knight)
+}
+
+/// Shared handle to a game. Both boards deliberately sit behind one lock:
+/// captures and promotions write to the partner board and its banks in the
+/// same step as the move itself, and the finished flag and clocks are shared.
+/// Per-board locks would have to be taken in a fixed order for every move
+/// anyway, and a move only holds the lock for microseconds.
+#[derive(Clone)]
+pub struct TandemGameInterface {
+    board: Arc<RwLock<TandemGame>>,
+    changed: Arc<Notify>,
+    move_latency: Arc<LatencyHistogram>,
+    slow_mode: Arc<SlowMode>,
+}
+
+impl TandemGameInterface {
+    pub fn new(time_control: TimeControl) -> Self {
+        TandemGameInterface {
+            board: Arc::new(RwLock::new(TandemGame::new(time_control))),
+            changed: Arc::new(Notify::new()),
+            move_latency: Arc::new(LatencyHistogram::new()),
+            slow_mode: Arc::new(SlowMode::new()),
+        }
+    }
+
+    /// A separate game for another room. The slow mode and the move latency
+    /// histogram are server wide, so they are shared with this one.
+    pub fn sibling(&self, time_control: TimeControl) -> Self {
+        TandemGameInterface {
+            board: Arc::new(RwLock::new(TandemGame::new(time_control))),
+            changed: Arc::new(Notify::new()),
+            move_latency: self.move_latency.clone(),
+            slow_mode: self.slow_mode.clone(),
+        }
+    }
+
+    /// Woken whenever a move, reset, abort or the end of the game changes
+    /// whose turn it is.
+    pub fn changed(&self) -> Arc<Notify> {
+        self.changed.clone()
+    }
+
+    pub fn turn_status(&self, board: u8, color: Color) -> Value {
+        self.board.read().unwrap().turn_status(board, color)
+    }
+
+    pub fn get_fen(&self, valid: bool) -> String {
+        self.board.read().unwrap().get_fen(valid)
+    }
+
+    pub fn get_snapshot(&self, reset: bool) -> String {
+        self.board.read().unwrap().get_snapshot(reset)
+    }
+
+    pub fn get_pgn(&self) -> String {
+        self.board.read().unwrap().get_pgn()
+    }
+
+    pub fn get_eval(&self) -> Value {
+        self.board.read().unwrap().get_eval()
+    }
+
+    pub fn get_clock(&self) -> String {
+        self.board.read().unwrap().get_clock()
+    }
+
+    pub fn get_fen_pair(&self) -> String {
+        self.board.read().unwrap().get_fen_pair()
+    }
+
+    pub fn last_move_san(&self, board: u8) -> String {
+        self.board.read().unwrap().last_move_san(board)
+    }
+
+    pub fn last_move(&self, board: u8) -> String {
+        self.board.read().unwrap().last_move(board)
+    }
+
+    pub fn time_left(&self, board: u8, color: Color) -> String {
+        self.board.write().unwrap().time_left(board, color)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.board.read().unwrap().finished
+    }
+
+    pub fn should_update(&self) -> bool {
+        let mut game = self.board.write().unwrap();
+        let was_finished = game.finished;
+        let update = game.should_update();
+
+        if !was_finished && game.finished {
+            self.changed.notify_waiters();
+        }
+
+        update
+    }
+
+    pub fn reset(&self) {
+        self.board.write().unwrap().reset();
+        self.changed.notify_waiters();
+    }
+
+    pub fn abort(&self) -> bool {
+        let aborted = self.board.write().unwrap().abort();
+
+        if aborted {
+            self.changed.notify_waiters();
+        }
+
+        aborted
+    }
+
+    pub fn change_setting(&self, key: &str, value: &str) -> bool {
+        self.board.write().unwrap().change_setting(key, value)
+    }
+
+    pub fn set_position(&self, board: &str, fen: &str, banks: &str) -> Result<(), &'static str> {
+        let result = self.board.write().unwrap().set_position(board, fen, banks);
+
+        if result.is_ok() {
+            self.changed.notify_waiters();
+        }
+
+        result
+    }
+
+    pub fn get_error(&self, reason: &str) -> String {
+        self.board.read().unwrap().get_error(reason)
+    }
+
+    pub fn mate_warning(&self, tandem_move: &TandemMove) -> Option<String> {
+        self.board.read().unwrap().mate_warning(tandem_move)
+    }
+
+    pub fn set_title(&self, title: &str) -> bool {
+        self.board.write().unwrap().set_title(title)
+    }
+
+    pub fn sit(&self, client_id: u64, board: u8, color: Color) -> Result<(), &'static str> {
+        self.board.write().unwrap().sit(client_id, board, color)
+    }
+
+    pub fn stand(&self, client_id: u64) -> bool {
+        self.board.write().unwrap().stand(client_id)
+    }
+
+    pub fn start_setup(&self) -> bool {
+        self.board.write().unwrap().start_setup()
+    }
+
+    pub fn place(&self, board: &str, square: &str, piece: &str) -> Result<(), &'static str> {
+        self.board.write().unwrap().place(board, square, piece)
+    }
+
+    pub fn lock(&self) -> Result<(), &'static str> {
+        let result = self.board.write().unwrap().lock();
+
+        if result.is_ok() {
+            self.changed.notify_waiters();
+        }
+
+        result
+    }
+
+    pub fn set_result(&self, outcome: &str, reason: &str) -> Result<(), &'static str> {
+        let result = self.board.write().unwrap().set_result(outcome, reason);
+
+        if result.is_ok() {
+            self.changed.notify_waiters();
+        }
+
+        result
+    }
+
+    pub fn offer_draw(&self, board: u8, color: Color) -> Result<(), &'static str> {
+        let result = self.board.write().unwrap().offer_draw(board, color);
+
+        if result.is_ok() {
+            self.changed.notify_waiters();
+        }
+
+        result
+    }
+
+    pub fn accept_draw(&self, board: u8, color: Color) -> Result<(), &'static str> {
+        let result = self.board.write().unwrap().accept_draw(board, color);
+
+        if result.is_ok() {
+            self.changed.notify_waiters();
+        }
+
+        result
+    }
+
+    pub fn resign(&self, board: u8, color: Color) -> Result<(), &'static str> {
+        let result = self.board.write().unwrap().resign(board, color);
+
+        if result.is_ok() {
+            self.changed.notify_waiters();
+        }
+
+        result
+    }
+
+    pub fn berserk(&self, board: u8, color: Color) -> bool {
+        self.board.write().unwrap().berserk(board, color)
+    }
+
+    pub fn log_event(&self, event: &str, details: Value) {
+        self.board.write().unwrap().log_event(event, details);
+    }
+
+    pub fn get_log(&self) -> Value {
+        self.board.read().unwrap().get_log()
+    }
+
+    pub fn get_rejection(&self, tandem_move: &TandemMove, reason: &str) -> String {
+        self.board.read().unwrap().get_rejection(tandem_move, reason)
+    }
+
+    /// Metrics of the whole server in the Prometheus text format.
+    pub fn get_metrics(&self) -> String {
+        self.move_latency.render("tandem_move_processing_seconds")
+    }
+
+    /// Caps the moves per second accepted across the server, `0` lifts it.
+    pub fn set_slow_mode(&self, moves_per_second: u32) {
+        self.slow_mode.set_cap(moves_per_second);
+        self.log_event("slow_mode", json!({ "moves_per_second": self.slow_mode.cap() }));
+    }
+
+    pub fn move_piece(&self, tandem_move: &TandemMove) -> Result<(), &'static str> {
+        // Checked before taking the game lock so throttled moves stay cheap.
+        if !self.slow_mode.admit() {
+            return Err("slow_mode");
+        }
+
+        let start = Instant::now();
+        let result = self.board.write().unwrap().move_piece(tandem_move);
+        self.move_latency.observe(start.elapsed());
+
+        if result.is_ok() {
+            self.changed.notify_waiters();
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut TandemGame, tandem_string: &str) -> Result<(), &'static str> {
+        game.move_piece(&TandemMove::from_string(tandem_string.to_owned()).unwrap())
+    }
+
+    #[test]
+    fn stalemate_draws_the_match_by_default() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        game.set_position("1", "7k/5K2/8/6Q1/8/8/8/8 w - - 0 1", "").unwrap();
+
+        play(&mut game, "1;W;g5;g6;wQ;").unwrap();
+
+        let result = game.result.as_ref().unwrap();
+        assert_eq!(game.status(), "drawn");
+        assert_eq!(result.event, ResultEvent::Stalemate);
+        assert_eq!(result.team_winner, None);
+        assert_eq!(result.board, Some(1));
+    }
+
+    #[test]
+    fn stalemate_loses_on_move_under_loss_policy() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        assert!(game.change_setting("stalemate", "loss"));
+        game.set_position("1", "7k/5K2/8/6Q1/8/8/8/8 w - - 0 1", "").unwrap();
+
+        play(&mut game, "1;W;g5;g6;wQ;").unwrap();
+
+        let result = game.result.as_ref().unwrap();
+        assert!(game.finished);
+        assert_eq!(result.event, ResultEvent::NoLegalMoves);
+        assert_eq!(result.team_winner, Some(Team::A));
+        assert_eq!(result.board, Some(1));
+    }
+
+    #[test]
+    fn boards_keep_independent_turn_orders() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        let sides = |game: &TandemGame| [game.games[0].board.side_to_move(), game.games[1].board.side_to_move()];
+
+        play(&mut game, "2;W;e2;e4;wP;").unwrap();
+        assert_eq!(sides(&game), [Color::White, Color::Black]);
+
+        play(&mut game, "2;B;e7;e5;bP;").unwrap();
+        play(&mut game, "2;W;g1;f3;wN;").unwrap();
+        assert_eq!(sides(&game), [Color::White, Color::Black]);
+
+        // Board 2 being on black's turn says nothing about board 1.
+        assert_eq!(play(&mut game, "1;B;d7;d5;bP;"), Err("wrong_turn"));
+        play(&mut game, "1;W;d2;d4;wP;").unwrap();
+        assert_eq!(sides(&game), [Color::Black, Color::Black]);
+
+        play(&mut game, "1;B;d7;d5;bP;").unwrap();
+        assert_eq!(play(&mut game, "1;B;g8;f6;bN;"), Err("wrong_turn"));
+        play(&mut game, "2;B;b8;c6;bN;").unwrap();
+        assert_eq!(sides(&game), [Color::White, Color::White]);
+
+        assert_eq!(play(&mut game, "2;B;g8;f6;bN;"), Err("wrong_turn"));
+        play(&mut game, "1;W;c2;c4;wP;").unwrap();
+        assert_eq!(sides(&game), [Color::Black, Color::White]);
+    }
+
+    #[test]
+    fn capturing_promotion_credits_capture_and_takes_partner_piece_once() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        game.set_position("1", "k6r/6P1/8/8/8/8/8/K7 w - - 0 1", "").unwrap();
+
+        play(&mut game, "1;W;g7;h8;wP;d1").unwrap();
+
+        let (board, partner) = (&game.games[0], &game.games[1]);
+        assert_eq!(board.board.piece_on(Square::H8), Some(Piece::Queen));
+        assert_eq!(board.board.color_on(Square::H8), Some(Color::White));
+        assert_eq!(partner.board.piece_on(Square::D1), None);
+
+        // The captured rook goes to the partner's bank, the partner board
+        // gets a single pawn for the queen it gave up, and nothing else.
+        assert_eq!(partner.bank_count(Color::Black, Piece::Rook), 1);
+        assert_eq!(partner.bank_count(Color::White, Piece::Pawn), 1);
+        assert_eq!(partner.black_sp.iter().sum::<i32>(), 1);
+        assert_eq!(partner.white_sp.iter().sum::<i32>(), 1);
+        assert!(board.bank_empty());
+    }
+
+    #[test]
+    fn retried_move_is_applied_once() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+
+        play(&mut game, "1;W;e2;e4;wP;;m1").unwrap();
+        let state = game.get_fen(true);
+
+        // The retry is acknowledged without touching the game.
+        assert_eq!(play(&mut game, "1;W;e2;e4;wP;;m1"), Ok(()));
+        assert_eq!(game.games[0].board.side_to_move(), Color::Black);
+        assert_eq!(game.get_fen(true), state);
+
+        // Without the id it's an ordinary move out of turn.
+        assert_eq!(play(&mut game, "1;W;e2;e4;wP;"), Err("wrong_turn"));
+    }
+
+    #[test]
+    fn move_on_missing_board_is_rejected() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+
+        for board in [0, 3] {
+            let tandem_move = TandemMove {
+                board,
+                color: Color::White,
+                source: "e2".to_owned(),
+                target: "e4".to_owned(),
+                piece: "wP".to_owned(),
+                promotion: String::new(),
+                id: Some("m1".to_owned()),
+            };
+
+            assert_eq!(game.move_piece(&tandem_move), Err("invalid_board"));
+        }
+    }
+
+    fn san(game: &TandemGame, board: u8) -> String {
+        game.games[(board - 1) as usize].last_move().unwrap().san.clone()
+    }
+
+    fn position(fen: &str, banks: &str) -> TandemGame {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        game.set_position("1", fen, banks).unwrap();
+
+        game
+    }
+
+    #[test]
+    fn san_of_pawn_and_piece_captures() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+
+        play(&mut game, "1;W;e2;e4;wP;").unwrap();
+        assert_eq!(san(&game, 1), "e4");
+        play(&mut game, "1;B;d7;d5;bP;").unwrap();
+        play(&mut game, "1;W;e4;d5;wP;").unwrap();
+        assert_eq!(san(&game, 1), "exd5");
+        play(&mut game, "1;B;d8;d5;bQ;").unwrap();
+        assert_eq!(san(&game, 1), "Qxd5");
+    }
+
+    #[test]
+    fn san_disambiguates_knights() {
+        let mut game = position("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "");
+        play(&mut game, "1;W;b1;d2;wN;").unwrap();
+        assert_eq!(san(&game, 1), "Nbd2");
+
+        let mut game = position("4k3/8/8/8/8/1N6/8/1N2K3 w - - 0 1", "");
+        play(&mut game, "1;W;b1;d2;wN;").unwrap();
+        assert_eq!(san(&game, 1), "N1d2");
+    }
+
+    #[test]
+    fn san_of_castling() {
+        let mut game = position("4k3/8/8/8/8/8/8/4K2R w K - 0 1", "");
+        play(&mut game, "1;W;e1;g1;wK;").unwrap();
+        assert_eq!(san(&game, 1), "O-O");
+
+        let mut game = position("r3k3/8/8/8/8/8/8/4K3 b q - 0 1", "");
+        play(&mut game, "1;B;e8;c8;bK;").unwrap();
+        assert_eq!(san(&game, 1), "O-O-O");
+    }
+
+    #[test]
+    fn san_of_promotion_with_check() {
+        let mut game = position("k7/6P1/8/8/8/8/8/K7 w - - 0 1", "");
+        play(&mut game, "1;W;g7;g8;wP;d1").unwrap();
+        assert_eq!(san(&game, 1), "g8=Q+");
+    }
+
+    #[test]
+    fn san_mate_suffix_follows_the_house_rule() {
+        let mut game = position("7k/5K2/8/8/8/8/8/6Q1 w - - 0 1", "");
+        play(&mut game, "1;W;g1;g7;wQ;").unwrap();
+        assert_eq!(san(&game, 1), "Qg7#");
+
+        // A distant rook could still be blocked by a drop, so it's only check.
+        let mut game = position("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "");
+        play(&mut game, "1;W;a1;a8;wR;").unwrap();
+        assert_eq!(san(&game, 1), "Ra8+");
+    }
+
+    #[test]
+    fn san_of_drops() {
+        let mut game = position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "N");
+        play(&mut game, "1;W;spare;e4;wN;").unwrap();
+        assert_eq!(san(&game, 1), "N@e4");
+    }
+
+    fn violations(game: &TandemGame) -> usize {
+        game.get_log().as_array().unwrap().iter()
+            .filter(|entry| entry["event"] == "conservation_violation")
+            .count()
+    }
+
+    #[test]
+    fn material_is_conserved_across_captures_en_passant_and_promotion() {
+        let mut game = position("r3k3/1P1p4/8/4P3/8/8/8/4K3 b - - 0 1", "");
+        let totals = game.material_totals();
+
+        for tandem_string in ["1;B;d7;d5;bP;", "1;W;e5;d6;wP;", "1;B;e8;f7;bK;", "1;W;b7;a8;wP;d1"] {
+            play(&mut game, tandem_string).unwrap();
+            assert_eq!(game.material_totals(), totals, "after {}", tandem_string);
+        }
+
+        // The en passant pawn and the rook went to the partner's bank, the
+        // promotion took the queen from d1 and handed a pawn back.
+        let partner = &game.games[1];
+        assert_eq!(partner.bank_count(Color::Black, Piece::Pawn), 1);
+        assert_eq!(partner.bank_count(Color::Black, Piece::Rook), 1);
+        assert_eq!(partner.bank_count(Color::White, Piece::Pawn), 1);
+        assert_eq!(partner.board.piece_on(Square::D1), None);
+        assert_eq!(violations(&game), 0);
+    }
+
+    #[test]
+    fn conservation_check_catches_lost_material() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        play(&mut game, "1;W;e2;e4;wP;").unwrap();
+        play(&mut game, "1;B;d7;d5;bP;").unwrap();
+
+        let totals = game.material_totals();
+        let tandem_move = TandemMove::from_string("1;W;e4;d5;wP;".to_owned()).unwrap();
+
+        // Simulates a capture that never reached the partner's bank.
+        game.move_piece(&tandem_move).unwrap();
+        assert_eq!(violations(&game), 0);
+        game.games[1].black_sp[4] -= 1;
+        game.check_conservation(totals, &tandem_move);
+
+        assert_eq!(violations(&game), 1);
+    }
+
+    #[test]
+    fn fischer_increment_builds_time_past_the_base() {
+        let mut game = TandemGame::new(TimeControl::parse("1+30").unwrap());
+
+        play(&mut game, "1;W;e2;e4;wP;").unwrap();
+
+        assert!(game.games[0].time_left(Color::White) > 60_000);
+    }
+
+    #[test]
+    fn custom_piece_values_change_the_evaluation() {
+        let mut game = position("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "N");
+        let default_eval = game.get_eval();
+
+        assert!(game.change_setting("piece_values", "1800,500,330,320,100"));
+
+        let eval = game.get_eval();
+        assert_eq!(eval["board_1"].as_i64().unwrap() - default_eval["board_1"].as_i64().unwrap(), 900);
+        assert_eq!(eval["board_2"], default_eval["board_2"]);
+
+        assert!(game.change_setting("piece_values", "900,500,330,1000,100"));
+        assert_eq!(game.get_eval()["board_1"].as_i64().unwrap() - default_eval["board_1"].as_i64().unwrap(), 680);
+    }
+
+    #[test]
+    fn promotion_without_partner_square_needs_auto_queen() {
+        let mut game = position("k7/6P1/8/8/8/8/8/K7 w - - 0 1", "");
+        assert_eq!(play(&mut game, "1;W;g7;g8;wP;"), Err("missing_promotion"));
+        assert_eq!(game.games[0].board.piece_on(Square::G7), Some(Piece::Pawn));
+
+        let mut game = position("k7/6P1/8/8/8/8/8/K7 w - - 0 1", "");
+        assert!(game.change_setting("auto_queen", "on"));
+        let partner_board = game.games[1].board;
+
+        play(&mut game, "1;W;g7;g8;wP;").unwrap();
+        assert_eq!(game.games[0].board.piece_on(Square::G8), Some(Piece::Queen));
+        assert_eq!(game.games[1].board, partner_board);
+        assert!(game.games[1].bank_empty());
+    }
+
+    #[test]
+    fn drops_while_in_check_have_to_block() {
+        let mut game = position("k3r3/8/8/8/8/8/8/4K3 w - - 0 1", "NP");
+
+        assert_eq!(play(&mut game, "1;W;spare;h3;wN;"), Err("in_check"));
+        assert_eq!(play(&mut game, "1;W;spare;e8;wN;"), Err("square_occupied"));
+        assert_eq!(game.games[0].bank_count(Color::White, Piece::Knight), 1);
+
+        play(&mut game, "1;W;spare;e4;wN;").unwrap();
+        assert_eq!(game.games[0].board.piece_on(Square::E4), Some(Piece::Knight));
+        assert_eq!(game.games[0].board.side_to_move(), Color::Black);
+    }
+
+    #[test]
+    fn promotion_needs_an_own_piece_from_the_partner_board() {
+        let mut game = position("k7/6P1/8/8/8/8/8/K7 w - - 0 1", "");
+        let partner_board = game.games[1].board;
+
+        for promotion in ["e4", "e2", "e1", "d8"] {
+            let tandem_string = format!("1;W;g7;g8;wP;{}", promotion);
+
+            assert_eq!(play(&mut game, &tandem_string), Err("invalid_promotion"), "from {}", promotion);
+            assert_eq!(game.games[1].board, partner_board);
+            assert!(game.games[0].bank_empty() && game.games[1].bank_empty());
+        }
+
+        assert_eq!(game.games[0].board.piece_on(Square::G7), Some(Piece::Pawn));
+    }
+
+    #[test]
+    fn rejected_drop_keeps_the_bank() {
+        let mut game = position("6rk/6pp/8/8/8/8/8/K7 w - - 0 1", "N");
+        assert_eq!(play(&mut game, "1;W;spare;f7;wN;"), Err("illegal_drop"));
+        assert_eq!(game.games[0].bank_count(Color::White, Piece::Knight), 1);
+        assert_eq!(game.games[0].board.piece_on(Square::F7), None);
+
+        let mut game = position("k3r3/8/8/8/8/8/8/4K3 w - - 0 1", "Q");
+        assert_eq!(play(&mut game, "1;W;spare;a4;wQ;"), Err("in_check"));
+        assert_eq!(game.games[0].bank_count(Color::White, Piece::Queen), 1);
+    }
+
+    #[test]
+    fn tandem_moves_round_trip_through_the_wire_format() {
+        for tandem_string in ["1;W;e2;e4;wP;", "2;B;spare;f6;bN;", "1;W;g7;g8;wP;d1", "2;W;b1;c3;wN;;m-17", "1;B;a2;a1;bP;h8;42"] {
+            let tandem_move = TandemMove::from_string(tandem_string.to_owned()).unwrap();
+
+            assert_eq!(tandem_move.to_string(), tandem_string);
+            assert_eq!(TandemMove::from_string(tandem_move.to_string()), Some(tandem_move));
+        }
+    }
+
+    #[test]
+    fn rejection_echoes_the_move_and_its_id() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        let tandem_move = TandemMove::from_string("1;W;e2;e5;wP;;m-3".to_owned()).unwrap();
+        let reason = game.move_piece(&tandem_move).unwrap_err();
+
+        let rejection: Value = serde_json::from_str(&game.get_rejection(&tandem_move, reason)).unwrap();
+
+        assert_eq!(rejection["type"], "update");
+        assert_eq!(rejection["valid"], false);
+        assert_eq!(rejection["rejected"], json!({
+            "board": 1,
+            "color": "W",
+            "source": "e2",
+            "target": "e5",
+            "piece": "wP",
+            "promotion": "",
+            "id": "m-3",
+            "reason": "illegal_move",
+        }));
+    }
+
+    #[test]
+    fn captures_past_the_bank_cap_are_discarded() {
+        let mut game = position("k7/8/8/1p6/8/8/1p6/1R5K w - - 0 1", "");
+        assert!(game.change_setting("bank_cap", "2,4,4,4,1"));
+
+        for tandem_string in ["1;W;b1;b2;wR;", "1;B;a8;a7;bK;", "1;W;b2;b5;wR;"] {
+            play(&mut game, tandem_string).unwrap();
+        }
+
+        assert_eq!(game.games[0].board.pieces(Piece::Pawn).popcnt(), 0);
+        assert_eq!(game.games[1].bank_count(Color::Black, Piece::Pawn), 1);
+    }
+
+    #[test]
+    fn abort_only_before_the_first_move() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        assert!(game.abort());
+        assert_eq!(game.status(), "aborted");
+        assert!(game.result.is_none());
+        assert!(play(&mut game, "1;W;e2;e4;wP;").is_err());
+
+        let mut game = TandemGame::new(TimeControl::default_control());
+        play(&mut game, "1;W;e2;e4;wP;").unwrap();
+        assert!(!game.abort());
+        assert_eq!(game.status(), "playing");
+    }
+
+    fn mate_on_both_boards(termination: &str) -> TandemGame {
+        let mut game = position("k7/7Q/1K6/8/8/8/8/8 w - - 0 1", "");
+        game.set_position("2", "K7/7q/1k6/8/8/8/8/8 b - - 0 1", "").unwrap();
+        assert!(game.change_setting("termination", termination));
+
+        game
+    }
+
+    #[test]
+    fn first_mate_ends_the_match_by_default() {
+        let mut game = mate_on_both_boards("first_board");
+
+        play(&mut game, "1;W;h7;b7;wQ;").unwrap();
+        assert_eq!(game.status(), "finished");
+        assert_eq!(game.result.as_ref().unwrap().team_winner, Some(Team::A));
+        assert_eq!(play(&mut game, "2;B;h7;b7;bQ;"), Err("game_finished"));
+    }
+
+    #[test]
+    fn mated_board_waits_for_the_other_under_both_boards() {
+        let mut game = mate_on_both_boards("both_boards");
+
+        play(&mut game, "1;W;h7;b7;wQ;").unwrap();
+        assert_eq!(game.status(), "playing");
+        assert_eq!(game.board_results, [Some(Team::A), None]);
+        assert_eq!(play(&mut game, "1;B;a8;b8;bK;"), Err("board_finished"));
+
+        play(&mut game, "2;B;h7;b7;bQ;").unwrap();
+        assert_eq!(game.status(), "finished");
+        assert_eq!(game.result.as_ref().unwrap().team_winner, Some(Team::A));
+    }
+
+    fn flag_black_on_board_1(flag: &str) -> TandemGame {
+        let mut game = position("k7/8/8/8/8/8/8/K6N w - - 0 1", "");
+        assert!(game.change_setting("flag", flag));
+
+        play(&mut game, "1;W;a1;a2;wK;").unwrap();
+        game.last_sync -= game.settings.time_control.base_ms + 1_000;
+        game.synchronize_time();
+
+        game
+    }
+
+    #[test]
+    fn flag_against_unmatable_side_is_a_draw_under_draw_if_unmatable() {
+        let game = flag_black_on_board_1("draw_if_unmatable");
+        let result = game.result.as_ref().unwrap();
+
+        assert_eq!((result.team_winner, result.event, result.board), (None, ResultEvent::FlagUnmatable, Some(1)));
+
+        let game = flag_black_on_board_1("loss");
+        let result = game.result.as_ref().unwrap();
+
+        assert_eq!((result.team_winner, result.event, result.board), (Some(Team::A), ResultEvent::Flag, Some(1)));
+    }
+
+    #[test]
+    fn increment_can_go_to_the_partner() {
+        let mut game = TandemGame::new(TimeControl::parse("1+30").unwrap());
+        assert!(game.change_setting("increment_to_partner", "on"));
+
+        play(&mut game, "1;W;e2;e4;wP;").unwrap();
+
+        assert!(game.games[0].time_left(Color::White) <= 60_000);
+        assert_eq!(game.games[1].time_left(Color::Black), 90_000);
+        assert_eq!(game.games[1].time_left(Color::White), 60_000);
+    }
+
+    #[test]
+    fn set_position_only_accepts_playable_positions() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+
+        assert_eq!(game.set_position("1", "k6R/8/1K6/8/8/8/8/8 b - - 0 1", ""), Err("terminal_position"));
+        assert_eq!(game.set_position("1", "k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", ""), Err("terminal_position"));
+        assert_eq!(game.set_position("1", "8/8/8/8/8/8/8/4K3 w - - 0 1", ""), Err("invalid_fen"));
+        assert_eq!(game.set_position("1", "P3k3/8/8/8/8/8/8/4K3 w - - 0 1", ""), Err("pawn_on_back_rank"));
+        assert_eq!(game.set_position("1", "4k3/8/8/8/8/8/8/4K3 w - - 0 1", "X"), Err("invalid_banks"));
+
+        game.set_position("2", "4k3/8/8/8/8/8/4P3/4K3 w - - 7 30", "Nq").unwrap();
+        assert_eq!(game.games[1].board.piece_on(Square::E2), Some(Piece::Pawn));
+        assert_eq!(game.games[1].bank_count(Color::White, Piece::Knight), 1);
+        assert_eq!(game.games[1].bank_count(Color::Black, Piece::Queen), 1);
+    }
+
+    #[test]
+    fn setup_builds_the_starting_position() {
+        let mut game = TandemGame::new(TimeControl::default_control());
+        let base_ms = game.settings.time_control.base_ms;
+
+        assert_eq!(game.place("1", "e4", "wN"), Err("not_in_setup"));
+        assert!(game.start_setup());
+        assert_eq!(game.status(), "setup");
+
+        game.place("1", "b1", "empty").unwrap();
+        game.place("1", "e4", "wN").unwrap();
+        assert_eq!(game.place("1", "h1", "wP"), Err("pawn_on_back_rank"));
+        assert_eq!(game.place("1", "e8", "empty"), Err("invalid_position"));
+        assert_eq!(game.games[0].board.piece_on(Square::H1), Some(Piece::Rook));
+
+        game.lock().unwrap();
+        assert_eq!(game.status(), "playing");
+        assert_eq!(game.place("1", "e5", "wN"), Err("not_in_setup"));
+
+        game.last_sync -= 1_000;
+        game.synchronize_time();
+        assert!(game.games[0].time_left(Color::White) < base_ms);
+        assert_eq!(game.games[0].time_left(Color::Black), base_ms);
+
+        play(&mut game, "1;W;e4;f6;wN;").unwrap();
+        assert_eq!(san(&game, 1), "Nf6+");
+        assert_eq!(game.games[0].board.piece_on(Square::B1), None);
+    }
+
+    fn bare_boards(board_1: &str) -> TandemGame {
+        let mut game = position(board_1, "");
+        game.set_position("2", "4k3/8/8/8/8/8/8/4K3 w - - 0 1", "").unwrap();
+
+        game
+    }
+
+    #[test]
+    fn dead_position_on_both_boards_is_a_draw() {
+        let mut game = bare_boards("4k3/8/8/8/8/8/8/3NK3 w - - 0 1");
+        play(&mut game, "1;W;e1;f2;wK;").unwrap();
+
+        let result = game.result.as_ref().unwrap();
+        assert_eq!((result.team_winner, result.event), (None, ResultEvent::DeadPosition));
+        assert_eq!(game.status(), "drawn");
+
+        // The captured queen goes to the partner's bank and can come back.
+        let mut game = bare_boards("4k3/8/8/8/8/8/3q4/4K3 w - - 0 1");
+        play(&mut game, "1;W;e1;d2;wK;").unwrap();
+        assert_eq!(game.status(), "playing");
+
+        let mut game = bare_boards("4k3/8/8/8/8/8/8/2BNK3 w - - 0 1");
+        play(&mut game, "1;W;e1;f2;wK;").unwrap();
+        assert_eq!(game.status(), "playing");
+
+        let mut game = bare_boards("4k3/8/8/8/8/8/8/3NK3 w - - 0 1");
+        assert!(game.change_setting("dead_position_draw", "off"));
+        play(&mut game, "1;W;e1;f2;wK;").unwrap();
+        assert_eq!(game.status(), "playing");
+    }
+
+    #[test]
+    fn banked_piece_without_a_legal_drop_is_not_droppable() {
+        let game = position("4k3/8/8/8/8/3n4/8/4K3 w - - 0 1", "NPq");
+        let banks = banks_json(&game.games[0]);
+
+        assert_eq!(banks["white"]["N"], json!({ "count": 1, "droppable": false }));
+        assert_eq!(banks["white"]["P"], json!({ "count": 1, "droppable": false }));
+        assert_eq!(banks["white"]["R"], json!({ "count": 0, "droppable": false }));
+        assert_eq!(banks["black"]["Q"], json!({ "count": 1, "droppable": true }));
+    }
+}
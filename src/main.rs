@@ -1,47 +1,258 @@
 mod game_server;
 
-use std::fs;
+use std::{env, fs, io, path::PathBuf, time::Duration};
 
 use axum::{
-    extract::Path,
+    extract::{Path, State},
     response::{Html, Response},
     routing::get,
-    Router, http::StatusCode,
+    Router, http::{HeaderMap, StatusCode},
     body::Body,
 };
 
+use serde::Deserialize;
+
+use crate::game_server::game_server::{DEFAULT_ROOM, Room, RoomMap, admin_secret_matches, connection_stats};
+use crate::game_server::tandem_game::parse_seat;
+
+static TURN_POLL_TIMEOUT: Duration = Duration::from_secs(25);
+static FILES_ROOT: &str = "./files";
+static DEFAULT_HTTP_ADDR: &str = "0.0.0.0:9090";
+static DEFAULT_WS_ADDR: &str = "0.0.0.0:9091";
+
+#[derive(Clone)]
+struct AppState {
+    rooms: RoomMap,
+    /// Port the websocket server listens on, handed to the web client.
+    ws_port: String,
+}
+
 #[tokio::main]
 async fn main() {
+    let http_addr = env_addr("TANDEM_HTTP_ADDR", DEFAULT_HTTP_ADDR);
+    let ws_addr = env_addr("TANDEM_WS_ADDR", DEFAULT_WS_ADDR);
+    let ws_port = ws_addr.rsplit_once(':').map(|(_, port)| port.to_owned()).unwrap_or_default();
+
+    let rooms = match game_server::game_server::start_server(&ws_addr) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Could not start the websocket server: {:?}", e);
+
+            return;
+        },
+    };
+
+    // Room endpoints take the room as their first segment, without it they
+    // serve the default room.
     let app = Router::new()
         .route("/", get(index))
-        .route("/files/{object}/{file_name}", get(return_file));
+        .route("/fen", get(fen))
+        .route("/fen/{room}", get(fen))
+        .route("/eval", get(eval))
+        .route("/eval/{room}", get(eval))
+        .route("/metrics", get(metrics))
+        .route("/pgn/{room}", get(pgn))
+        .route("/turn/{board}/{color}", get(turn))
+        .route("/turn/{room}/{board}/{color}", get(turn))
+        .route("/admin/log", get(admin_log))
+        .route("/admin/log/{room}", get(admin_log))
+        .route("/admin/connections", get(admin_connections))
+        .route("/admin/connections/{room}", get(admin_connections))
+        .route("/files/{object}/{file_name}", get(return_file))
+        .with_state(AppState { rooms, ws_port });
 
-    game_server::game_server::start_server();
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:9090").await.unwrap();
+    let listener = tokio::net::TcpListener::bind(&http_addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
-async fn index() -> Html<String> {
+/// Serves the client with the websocket port filled in, so it follows
+/// `TANDEM_WS_ADDR`.
+async fn index(State(state): State<AppState>) -> Html<String> {
     let content_home = content("./files/html/index.html");
 
-    Html(content_home)
+    Html(content_home.replace("{{WS_PORT}}", &state.ws_port))
 }
 
-async fn return_file(Path((object, file_name)): Path<(String, String)>) -> Response {
-    let file_path = format!("./files/{object}/{file_name}");
+/// Room named in the path, or the default room for routes without one.
+fn find_room(state: &AppState, room: Option<&str>) -> Option<Room> {
+    state.rooms.read().unwrap().get(room.unwrap_or(DEFAULT_ROOM)).cloned()
+}
+
+fn not_found() -> Response {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from(""))
+        .unwrap()
+}
+
+async fn fen(State(state): State<AppState>, room: Option<Path<String>>) -> Response {
+    let room = match find_room(&state, room.as_deref().map(String::as_str)) {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(Body::from(room.game.get_fen_pair()))
+        .unwrap()
+}
+
+async fn eval(State(state): State<AppState>, room: Option<Path<String>>) -> Response {
+    let room = match find_room(&state, room.as_deref().map(String::as_str)) {
+        Some(v) => v,
+        None => return not_found(),
+    };
 
-    let file = fs::read(&file_path[..]);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(room.game.get_eval().to_string()))
+        .unwrap()
+}
+
+/// The move metrics are shared by all rooms, so any room's game renders
+/// them.
+async fn metrics(State(state): State<AppState>) -> Response {
+    let room = match find_room(&state, None) {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(Body::from(room.game.get_metrics()))
+        .unwrap()
+}
+
+/// Both boards of a room's current game, or of its last one right after a
+/// reset, as PGN.
+async fn pgn(State(state): State<AppState>, Path(room): Path<String>) -> Response {
+    let room = match find_room(&state, Some(&room)) {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/x-chess-pgn")
+        .body(Body::from(room.game.get_pgn()))
+        .unwrap()
+}
+
+#[derive(Deserialize)]
+struct TurnPath {
+    room: Option<String>,
+    board: String,
+    color: String,
+}
+
+/// Long-polls until it's the given seat's turn or the game is over, answering
+/// `204 No Content` if neither happens within the timeout.
+async fn turn(State(state): State<AppState>, Path(path): Path<TurnPath>) -> Response {
+    let (board, color) = match parse_seat(&path.board, &path.color) {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    let room = match find_room(&state, path.room.as_deref()) {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    let changed = room.game.changed();
+    let deadline = tokio::time::Instant::now() + TURN_POLL_TIMEOUT;
+
+    loop {
+        // Registered before looking at the game so a move in between isn't missed.
+        let notified = changed.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let turn_status = room.game.turn_status(board, color);
+
+        if turn_status["status"] != "waiting" {
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(turn_status.to_string()))
+                .unwrap();
+        }
+
+        if tokio::time::timeout_at(deadline, notified).await.is_err() {
+            return Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::from(""))
+                .unwrap();
+        }
+    }
+}
+
+async fn admin_log(State(state): State<AppState>, room: Option<Path<String>>, headers: HeaderMap) -> Response {
+    if !admin_authorized(&headers) {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(""))
+            .unwrap();
+    }
+
+    let room = match find_room(&state, room.as_deref().map(String::as_str)) {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(room.game.get_log().to_string()))
+        .unwrap()
+}
+
+async fn admin_connections(State(state): State<AppState>, room: Option<Path<String>>, headers: HeaderMap) -> Response {
+    if !admin_authorized(&headers) {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(""))
+            .unwrap();
+    }
+
+    let room = match find_room(&state, room.as_deref().map(String::as_str)) {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(connection_stats(&room.clients).to_string()))
+        .unwrap()
+}
+
+/// Admin routes are only reachable when the request carries the admin secret
+/// in the `x-admin-secret` header.
+fn admin_authorized(headers: &HeaderMap) -> bool {
+    match headers.get("x-admin-secret").and_then(|v| v.to_str().ok()) {
+        Some(v) => admin_secret_matches(v),
+        None => false,
+    }
+}
+
+async fn return_file(Path((object, file_name)): Path<(String, String)>) -> Response {
+    let file = match resolve_file(&object, &file_name) {
+        Some(path) => fs::read(&path),
+        None => Err(io::Error::from(io::ErrorKind::NotFound)),
+    };
 
     match file {
         Ok(v) => 
         Response::builder()
             .status(StatusCode::OK)
-            .header("content-type", "text/".to_owned() + &object)
+            .header("content-type", content_type(&file_name))
             .body(Body::from(v))
             .unwrap(),
         Err(e) => {
-            println!("File not found {}: {:?}", file_path, e);
+            println!("File not found {}/{}: {:?}", object, file_name, e);
 
             Response::builder()
                 .status(StatusCode::NOT_FOUND)
@@ -51,8 +262,93 @@ async fn return_file(Path((object, file_name)): Path<(String, String)>) -> Respo
     }
 }
 
+/// Maps the two path segments to a file under `FILES_ROOT`. Anything that
+/// could climb out of it, or resolves outside of it through a symlink, is
+/// treated as missing.
+fn resolve_file(object: &str, file_name: &str) -> Option<PathBuf> {
+    let unsafe_segment = |segment: &str| {
+        segment.is_empty() || segment == "." || segment.contains("..")
+            || segment.contains(['/', '\\', '\0'])
+    };
+
+    if unsafe_segment(object) || unsafe_segment(file_name) {
+        return None;
+    }
+
+    let root = fs::canonicalize(FILES_ROOT).ok()?;
+    let path = fs::canonicalize(root.join(object).join(file_name)).ok()?;
+
+    path.starts_with(&root).then_some(path)
+}
+
+/// MIME type for a served file, picked by its extension.
+fn content_type(file_name: &str) -> &'static str {
+    let extension = file_name.rsplit_once('.').map(|(_, v)| v.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        Some("woff2") => "font/woff2",
+        Some("ico") => "image/x-icon",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Listen address from the environment, or the default if it isn't set.
+fn env_addr(key: &str, default: &str) -> String {
+    match env::var(key) {
+        Ok(v) if !v.is_empty() => v,
+        _ => default.to_owned(),
+    }
+}
+
 fn content(file_path: &str) -> String {
-    let content = fs::read_to_string(file_path).expect("Valid file");
+    fs::read_to_string(file_path).expect("Valid file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_file_stays_inside_the_files_root() {
+        let path = resolve_file("html", "index.html").unwrap();
+        assert!(path.starts_with(fs::canonicalize(FILES_ROOT).unwrap()));
+
+        let escapes = [
+            ("..", "Cargo.toml"),
+            ("html", ".."),
+            ("html/..", "index.html"),
+            ("html", "../Cargo.toml"),
+            ("html", "..\\Cargo.toml"),
+            ("html", "index.html\0"),
+            ("", "index.html"),
+            (".", "html"),
+        ];
+
+        for (object, file_name) in escapes {
+            assert_eq!(resolve_file(object, file_name), None, "{:?}/{:?}", object, file_name);
+        }
 
-    content
-}
\ No newline at end of file
+        assert_eq!(resolve_file("html", "missing.html"), None);
+    }
+
+    #[test]
+    fn content_type_follows_the_extension() {
+        assert_eq!(content_type("index.html"), "text/html; charset=utf-8");
+        assert_eq!(content_type("chessboard-1.0.0.min.css"), "text/css");
+        assert_eq!(content_type("jquery-3.7.1.min.js"), "application/javascript");
+        assert_eq!(content_type("wK.PNG"), "image/png");
+        assert_eq!(content_type("Move.mp3"), "audio/mpeg");
+        assert_eq!(content_type("Move.ogg"), "audio/ogg");
+        assert_eq!(content_type("README"), "application/octet-stream");
+        assert_eq!(content_type("archive.tar.gz"), "application/octet-stream");
+    }
+}